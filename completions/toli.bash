@@ -18,7 +18,7 @@ _toli() {
 
     case "${cmd}" in
         toli)
-            opts=" --how --do --explain --alias --version"
+            opts=" --how --do --sandbox --explain --file --breakdown --alias --min-confidence --ensemble --verbose --profile --config --version"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
                 return 0
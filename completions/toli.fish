@@ -1,5 +1,13 @@
 complete -c toli -l how -d 'Get help on how to perform a task'
 complete -c toli -l do -d 'Execute a task based on natural language description'
+complete -c toli -l sandbox -d 'Dry-run the command in a sandbox before running it'
 complete -c toli -l explain -d 'Explain what a given command does'
+complete -c toli -s f -l file -r -F -d 'Explain the script in a file'
+complete -c toli -s b -l breakdown -d 'Explain a command word by word'
 complete -c toli -l alias -d 'Suggest aliases for a given command'
+complete -c toli -l min-confidence -r -d 'Hide suggestions below this confidence'
+complete -c toli -l ensemble -d 'Ask several models and merge their suggestions'
+complete -c toli -s v -l verbose -d 'Print token usage, timing and estimated cost'
+complete -c toli -s p -l profile -r -d 'Use a named configuration profile'
+complete -c toli -s c -l config -r -F -d 'Read the configuration from a file'
 complete -c toli -l version -d 'Show version information'
\ No newline at end of file
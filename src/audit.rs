@@ -0,0 +1,264 @@
+//! An append-only log of the commands toli executes. Each record carries the
+//! hash of the one before it, so a record that is changed, removed or moved
+//! breaks the chain and `toli audit verify` reports where.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use crate::config;
+use crate::llm::RawReply;
+use crate::usage;
+
+/// The `prev_hash` of the first record.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The `[audit]` section: whether executed commands are logged, and where.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub enabled: bool,
+    /// Log location, e.g. in a shared team directory; defaults to
+    /// `audit.jsonl` in toli's data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<PathBuf>,
+}
+
+impl AuditConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn log_path(&self) -> Result<PathBuf> {
+        match &self.log {
+            Some(path) => Ok(config::expand_home(path)),
+            None => Ok(dirs::data_dir()
+                .ok_or_else(|| anyhow!("Could not determine the data directory"))?
+                .join("toli")
+                .join("audit.jsonl")),
+        }
+    }
+}
+
+/// One executed command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub user: String,
+    pub host: String,
+    pub cwd: String,
+    pub query: String,
+    /// The backends and models that answered, e.g. `Ollama` and `llama3.2`;
+    /// comma-separated when several did.
+    pub backend: String,
+    pub model: String,
+    /// The prompt versions the replies were made with; comma-separated when
+    /// they differ, and left out when no prompts were used.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prompt_version: String,
+    /// The models' replies to the query as sent, one per paragraph.
+    pub response: String,
+    pub command: String,
+    /// `None` when the command could not be started or was killed by a signal.
+    pub exit_status: Option<i32>,
+    pub prev_hash: String,
+    /// SHA-256 of the record without this field, in hex.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+impl AuditRecord {
+    /// A record of `command`, run for `query` on the strength of `replies`.
+    /// The chain fields are filled in by [`AuditLog::append`].
+    pub fn new(query: &str, command: &str, replies: &[RawReply], exit_status: Option<i32>) -> Self {
+        let replies: Vec<&RawReply> = replies.iter().filter(|reply| reply.task == "translate").collect();
+        let names = |name: fn(&RawReply) -> &str| {
+            let mut names: Vec<&str> = Vec::new();
+            for reply in &replies {
+                if !name(reply).is_empty() && !names.contains(&name(reply)) {
+                    names.push(name(reply));
+                }
+            }
+            names.join(", ")
+        };
+        Self {
+            timestamp: usage::now(),
+            user: first_env(&["USER", "LOGNAME", "USERNAME"]).unwrap_or_else(|| String::from("unknown")),
+            host: hostname(),
+            cwd: std::env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default(),
+            query: query.to_string(),
+            backend: names(|reply| &reply.backend),
+            model: names(|reply| &reply.model),
+            prompt_version: names(|reply| &reply.prompt_version),
+            response: replies.iter().map(|reply| reply.content.as_str()).collect::<Vec<_>>().join("\n\n"),
+            command: command.to_string(),
+            exit_status,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    /// The hash this record should have, given its other fields.
+    pub fn compute_hash(&self) -> String {
+        let unhashed = Self { hash: String::new(), ..self.clone() };
+        let json = serde_json::to_vec(&unhashed).unwrap_or_default();
+        Sha256::digest(&json).iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+}
+
+/// Audit records, one JSON object per line.
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn from_config(config: &AuditConfig) -> Result<Self> {
+        Ok(Self::new(config.log_path()?))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fails unless records can be appended, so nothing runs unlogged.
+    pub fn check(&self) -> Result<()> {
+        self.open().map(|_| ())
+    }
+
+    /// Chains `record` to the last one in the log and appends it. The log is
+    /// locked meanwhile, so several users can share it.
+    pub fn append(&self, mut record: AuditRecord) -> Result<AuditRecord> {
+        let mut file = self.open()?;
+        file.lock().map_err(|e| anyhow!("Failed to lock audit log {}: {}", self.path.display(), e))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map_err(|e| anyhow!("Failed to read audit log {}: {}", self.path.display(), e))?;
+        record.prev_hash = match contents.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => serde_json::from_str::<AuditRecord>(line)
+                .map_err(|_| anyhow!("The last line of audit log {} is not a valid record", self.path.display()))?
+                .hash,
+            None => String::from(GENESIS),
+        };
+        record.hash = record.compute_hash();
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .map_err(|e| anyhow!("Failed to write audit log {}: {}", self.path.display(), e))?;
+        Ok(record)
+    }
+
+    /// All records, oldest first.
+    pub fn records(&self) -> Result<Vec<AuditRecord>> {
+        self.lines()?
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| anyhow!("Line {} of audit log {} is not a valid record: {}", i + 1, self.path.display(), e))
+            })
+            .collect()
+    }
+
+    /// Checks every record's hash and its link to the record before it,
+    /// returning the number of records.
+    pub fn verify(&self) -> Result<usize> {
+        let mut prev_hash = String::from(GENESIS);
+        let mut count = 0;
+        for (i, line) in self.lines()?.iter().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let record: AuditRecord = serde_json::from_str(line)
+                .map_err(|_| anyhow!("line {} is not a valid audit record", i + 1))?;
+            if record.prev_hash != prev_hash {
+                return Err(anyhow!(
+                    "line {} does not link to the record before it; records were changed, removed, inserted or reordered",
+                    i + 1
+                ));
+            }
+            if record.hash != record.compute_hash() {
+                return Err(anyhow!("line {} was changed after it was written", i + 1));
+            }
+            prev_hash = record.hash;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn open(&self) -> Result<std::fs::File> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(|e| anyhow!("Failed to open audit log {}: {}", self.path.display(), e))
+    }
+
+    fn lines(&self) -> Result<Vec<String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(anyhow!("Failed to read audit log {}: {}", self.path.display(), e)),
+        }
+    }
+}
+
+/// `records` as CSV with a header row.
+pub fn to_csv(records: &[AuditRecord]) -> String {
+    let mut csv = String::from("timestamp,user,host,cwd,query,backend,model,prompt_version,response,command,exit_status,prev_hash,hash\n");
+    for record in records {
+        let fields = [
+            record.timestamp.to_string(),
+            record.user.clone(),
+            record.host.clone(),
+            record.cwd.clone(),
+            record.query.clone(),
+            record.backend.clone(),
+            record.model.clone(),
+            record.prompt_version.clone(),
+            record.response.clone(),
+            record.command.clone(),
+            record.exit_status.map(|status| status.to_string()).unwrap_or_default(),
+            record.prev_hash.clone(),
+            record.hash.clone(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn first_env(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| first_env(&["HOSTNAME", "COMPUTERNAME"]))
+        .unwrap_or_else(|| String::from("unknown"))
+}
@@ -0,0 +1,351 @@
+//! Flag-by-flag explanations in the style of explainshell: the command is split
+//! into segments locally, the model explains each word, and the result is
+//! rendered with every explanation aligned under its token.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use crate::engine;
+use crate::shell::{self, Command, CompoundCommand, Redirect, RedirectOp, RedirectTarget, SimpleCommand, Word};
+
+/// Programs whose first plain argument selects a subcommand, e.g. `git commit`.
+const SUBCOMMAND_PROGRAMS: &[&str] = &[
+    "apt", "apt-get", "brew", "cargo", "dnf", "docker", "gh", "git", "go", "helm", "kubectl", "npm",
+    "pip", "pip3", "pnpm", "podman", "systemctl", "terraform", "yarn", "yum",
+];
+
+/// Operators that separate commands, longest first.
+const OPERATORS: &[&str] = &["&&", "||", "|&", ";;", "|", ";", "&"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Program,
+    Subcommand,
+    Flag,
+    Argument,
+    /// `NAME=value` before a program.
+    Assignment,
+    /// `|`, `&&`, `||`, `;` or `&`.
+    Operator,
+    /// `>`, `>>`, `<`, `2>` or `2>&1`.
+    Redirect,
+    /// A reserved word or other shell syntax, e.g. `if`, `do` or `{`.
+    Keyword,
+}
+
+impl SegmentKind {
+    fn name(self) -> &'static str {
+        match self {
+            SegmentKind::Program => "program",
+            SegmentKind::Subcommand => "subcommand",
+            SegmentKind::Flag => "flag",
+            SegmentKind::Argument => "argument",
+            SegmentKind::Assignment => "variable",
+            SegmentKind::Operator => "operator",
+            SegmentKind::Redirect => "redirect",
+            SegmentKind::Keyword => "keyword",
+        }
+    }
+}
+
+/// A token of the command as written, quotes included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Segment {
+    pub text: String,
+    /// Byte offset of the segment in the command.
+    pub start: usize,
+    pub kind: SegmentKind,
+}
+
+/// A segment with its explanation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Part {
+    pub segment: Segment,
+    pub explanation: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Breakdown {
+    pub command: String,
+    pub summary: String,
+    pub parts: Vec<Part>,
+}
+
+/// The reply the model is asked for.
+#[derive(Debug, Deserialize)]
+struct Reply {
+    #[serde(default)]
+    summary: String,
+    segments: Vec<Explained>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Explained {
+    index: usize,
+    explanation: String,
+}
+
+/// Splits `command` into segments, or `None` when it can't be parsed
+/// (e.g. unbalanced quotes).
+pub fn segments(command: &str) -> Option<Vec<Segment>> {
+    let list = shell::parse(command).ok()?;
+    let mut segments = Vec::new();
+    for pipeline in list.statements() {
+        for item in &pipeline.commands {
+            command_segments(command, item, &mut segments);
+        }
+    }
+
+    // What lies between the words is operators and reserved words, apart
+    // from comments and here-document bodies.
+    let mut covered: Vec<Range<usize>> = segments.iter().map(|s| s.start..s.start + s.text.len()).collect();
+    covered.extend(list.here_docs().iter().map(|heredoc| heredoc.span.clone()));
+    covered.sort_by_key(|range| range.start);
+    let mut position = 0;
+    for range in covered {
+        if range.start > position {
+            syntax_segments(command, position..range.start, &mut segments);
+        }
+        position = position.max(range.end);
+    }
+    syntax_segments(command, position..command.len(), &mut segments);
+    segments.sort_by_key(|segment| segment.start);
+    Some(segments)
+}
+
+fn command_segments(source: &str, command: &Command, segments: &mut Vec<Segment>) {
+    let words: Vec<&Word> = match command {
+        Command::Simple(simple) => return simple_segments(source, simple, segments),
+        Command::Function { body, .. } => return command_segments(source, body, segments),
+        Command::Compound(CompoundCommand::For { words, .. }, _) => words.iter().flatten().collect(),
+        Command::Compound(CompoundCommand::Case { word, arms }, _) => {
+            std::iter::once(word).chain(arms.iter().flat_map(|arm| &arm.patterns)).collect()
+        }
+        Command::Compound(CompoundCommand::Conditional(words), _) => words.iter().collect(),
+        Command::Compound(..) => Vec::new(),
+    };
+    for word in words {
+        let kind = if is_flag(&word.value()) { SegmentKind::Flag } else { SegmentKind::Argument };
+        push(source, word.span.clone(), kind, segments);
+    }
+    for redirect in command.redirects() {
+        redirect_segments(source, redirect, segments);
+    }
+}
+
+fn simple_segments(source: &str, command: &SimpleCommand, segments: &mut Vec<Segment>) {
+    for assignment in &command.assignments {
+        push(source, assignment.span.clone(), SegmentKind::Assignment, segments);
+    }
+    let values: Vec<String> = command.words.iter().map(Word::value).collect();
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+    let start = shell::wrapped_program(&values);
+    let program = values.get(start).map_or("", |p| p.rsplit('/').next().unwrap_or(p));
+    let mut subcommand_seen = false;
+    for (i, (word, value)) in command.words.iter().zip(&values).enumerate() {
+        let name = value.rsplit('/').next().unwrap_or(value);
+        let kind = if i == start || (i < start && shell::WRAPPERS.contains(&name)) {
+            SegmentKind::Program
+        } else if is_flag(value) {
+            SegmentKind::Flag
+        } else if i < start && is_assignment(value) {
+            SegmentKind::Assignment
+        } else if i > start && !subcommand_seen && SUBCOMMAND_PROGRAMS.contains(&program) {
+            subcommand_seen = true;
+            SegmentKind::Subcommand
+        } else {
+            SegmentKind::Argument
+        };
+        push(source, word.span.clone(), kind, segments);
+    }
+    for redirect in &command.redirects {
+        redirect_segments(source, redirect, segments);
+    }
+}
+
+/// The operator of a redirection and the file it names, or the whole of a
+/// descriptor duplication like `2>&1` or a here-document's `<<EOF`.
+fn redirect_segments(source: &str, redirect: &Redirect, segments: &mut Vec<Segment>) {
+    match &redirect.target {
+        RedirectTarget::Word(target) if !matches!(redirect.op, RedirectOp::DupInput | RedirectOp::DupOutput) => {
+            let operator = source[redirect.span.start..target.span.start].trim_end();
+            push(source, redirect.span.start..redirect.span.start + operator.len(), SegmentKind::Redirect, segments);
+            push(source, target.span.clone(), SegmentKind::Argument, segments);
+        }
+        _ => push(source, redirect.span.clone(), SegmentKind::Redirect, segments),
+    }
+}
+
+/// Operators and reserved words in `range` of `source`, skipping comments and
+/// escaped newlines.
+fn syntax_segments(source: &str, range: Range<usize>, segments: &mut Vec<Segment>) {
+    let text = &source[range.clone()];
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let len = if c.is_whitespace() || rest.starts_with("\\\n") {
+            i += if c == '\\' { 2 } else { c.len_utf8() };
+            continue;
+        } else if c == '#' {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+            push(source, range.start + i..range.start + i + operator.len(), SegmentKind::Operator, segments);
+            operator.len()
+        } else {
+            let len = rest.find(|c: char| c.is_whitespace() || matches!(c, '|' | '&' | ';')).unwrap_or(rest.len());
+            push(source, range.start + i..range.start + i + len, SegmentKind::Keyword, segments);
+            len
+        };
+        i += len;
+    }
+}
+
+fn push(source: &str, range: Range<usize>, kind: SegmentKind, segments: &mut Vec<Segment>) {
+    segments.push(Segment { text: source[range.clone()].to_string(), start: range.start, kind });
+}
+
+/// The segments the model is asked about, one per line as `N. text (kind)`.
+/// Operators and redirects are explained locally and left out.
+pub fn numbered(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .filter(|(_, segment)| !is_local(segment))
+        .map(|(i, segment)| format!("{}. {} ({})", i + 1, segment.text, segment.kind.name()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Builds a breakdown from a model reply of the form
+/// `{"summary": "...", "segments": [{"index": 1, "explanation": "..."}]}`,
+/// tolerating surrounding prose. `None` when the reply explains none of the segments.
+pub fn parse_reply(command: &str, segments: &[Segment], reply: &str) -> Option<Breakdown> {
+    let reply = reply.trim();
+    let object = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => return None,
+    };
+    let parsed: Reply = serde_json::from_str(object)
+        .or_else(|_| serde_json::from_str(&engine::escape_raw_control_chars(object)))
+        .ok()?;
+    if !parsed.segments.iter().any(|e| (1..=segments.len()).contains(&e.index)) {
+        return None;
+    }
+
+    let parts = segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let explanation = match local_explanation(segment) {
+                Some(explanation) => explanation.to_string(),
+                None => parsed
+                    .segments
+                    .iter()
+                    .find(|e| e.index == i + 1)
+                    .map(|e| e.explanation.trim().to_string())
+                    .unwrap_or_default(),
+            };
+            Part { segment: segment.clone(), explanation }
+        })
+        .collect();
+    Some(Breakdown { command: command.to_string(), summary: parsed.summary.trim().to_string(), parts })
+}
+
+impl Breakdown {
+    /// A breakdown with the segments of `command` but no explanations for
+    /// them, e.g. when only a prose `summary` is available.
+    pub fn unexplained(command: &str, summary: String) -> Self {
+        let parts = segments(command)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|segment| Part { explanation: local_explanation(&segment).unwrap_or_default().to_string(), segment })
+            .collect();
+        Self { command: command.to_string(), summary, parts }
+    }
+
+    /// The command with each explanation hanging under its token:
+    ///
+    /// ```text
+    /// ls -la | wc -l
+    /// │  │   │ │  └─ count lines
+    /// │  │   │ └─ word count
+    /// ...
+    /// ```
+    ///
+    /// Commands spanning several lines are listed token by token instead.
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        if self.command.contains('\n') {
+            let width = self.parts.iter().map(|p| p.segment.text.chars().count()).max().unwrap_or(0).min(24);
+            for part in &self.parts {
+                lines.push(format!("{:<width$}  {}", part.segment.text, describe(part), width = width));
+            }
+        } else {
+            lines.push(self.command.clone());
+            let columns: Vec<usize> = self.parts.iter().map(|p| self.command[..p.segment.start].chars().count()).collect();
+            for (i, part) in self.parts.iter().enumerate().rev() {
+                let mut line: Vec<char> = vec![' '; columns[i]];
+                for &column in &columns[..i] {
+                    line[column] = '│';
+                }
+                let mut line: String = line.into_iter().collect();
+                line.push_str("└─ ");
+                line.push_str(&describe(part));
+                lines.push(line.trim_end().to_string());
+            }
+        }
+        if !self.summary.is_empty() {
+            lines.push(String::new());
+            lines.push(self.summary.clone());
+        }
+        lines.join("\n")
+    }
+}
+
+fn describe(part: &Part) -> String {
+    if part.explanation.is_empty() {
+        String::from("(no explanation)")
+    } else {
+        part.explanation.clone()
+    }
+}
+
+fn is_local(segment: &Segment) -> bool {
+    local_explanation(segment).is_some()
+}
+
+fn local_explanation(segment: &Segment) -> Option<&'static str> {
+    if !matches!(segment.kind, SegmentKind::Operator | SegmentKind::Redirect) {
+        return None;
+    }
+    Some(match segment.text.as_str() {
+        "|" => "pipe: passes the output on the left to the command on the right",
+        "|&" => "pipe: passes the output and error output on the left to the command on the right",
+        "&&" => "runs the next command only if the previous one succeeded",
+        "||" => "runs the next command only if the previous one failed",
+        ";" => "runs the next command after the previous one finishes",
+        "&" => "runs the previous command in the background",
+        ">" => "writes output to the file that follows, replacing its contents",
+        ">>" => "appends output to the file that follows",
+        "<" => "reads input from the file that follows",
+        "2>" => "writes error output to the file that follows",
+        "2>&1" => "sends error output to the same place as normal output",
+        _ => return None,
+    })
+}
+
+fn is_flag(token: &str) -> bool {
+    token.len() > 1 && token.starts_with('-')
+}
+
+fn is_assignment(token: &str) -> bool {
+    match token.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::llm::{ResponseType, Suggestion};
+use crate::shell::{self, Command, List, Redirect, RedirectOp, RedirectTarget, Word};
+
+/// How much the confidence of a command line toli can't parse is lowered.
+const UNPARSED_PENALTY: f32 = 0.2;
+
+/// Confidence cutoffs for classifying suggestions, set in the `[thresholds]`
+/// section of the config.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Minimum confidence for a suggestion to be offered as a ready-to-run command.
+    pub command: f64,
+    /// Minimum confidence for a suggestion to be offered at all rather than
+    /// marked uncertain.
+    pub script: f64,
+    /// Suggestions below this confidence are hidden; `--min-confidence` overrides it.
+    pub min_confidence: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self { command: 0.8, script: 0.5, min_confidence: 0.0 }
+    }
+}
+
+impl Thresholds {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("command", self.command), ("script", self.script), ("min_confidence", self.min_confidence)] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(anyhow!("thresholds.{} must be between 0 and 1, got {}", name, value));
+            }
+        }
+        if self.script > self.command {
+            return Err(anyhow!(
+                "thresholds.script ({}) must not be higher than thresholds.command ({})",
+                self.script, self.command
+            ));
+        }
+        Ok(())
+    }
+
+    /// Classifies a suggestion by the model's confidence, without trusting it
+    /// blindly: multi-line content and shebangs are always scripts, and a
+    /// command line toli can't parse is trusted less and never ready to run,
+    /// since it may still be valid in a shell the parser doesn't fully know.
+    pub fn classify(&self, suggestion: Suggestion) -> ResponseType {
+        let command = suggestion.option.command.trim();
+        let script = is_script(command);
+        let parsed = script || shell::parse(command).is_ok();
+        // Compare at the model's precision so a confidence of 0.7 meets a 0.7 threshold.
+        let confidence = if parsed { suggestion.option.confidence } else { suggestion.option.confidence - UNPARSED_PENALTY };
+
+        if command.is_empty() || confidence < self.script as f32 {
+            ResponseType::Uncertain(suggestion)
+        } else if script || !parsed || confidence < self.command as f32 {
+            ResponseType::ScriptRecommended(suggestion)
+        } else {
+            ResponseType::Command(suggestion)
+        }
+    }
+}
+
+/// Whether `command` is a script rather than a single command line.
+pub fn is_script(command: &str) -> bool {
+    let command = command.trim();
+    command.starts_with("#!") || command.lines().filter(|l| !l.trim().is_empty()).count() > 1
+}
+
+/// How much damage a command could do if it isn't what the user wanted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+/// What a command mostly acts on. When a command line has several parts, the
+/// most far-reaching one decides (later variants win).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// Only reads files or shows information.
+    Inspect,
+    /// A program toli doesn't know about.
+    Other,
+    /// Creates, changes or removes files.
+    Filesystem,
+    /// Starts, stops or signals processes.
+    Process,
+    /// Talks to other machines.
+    Network,
+    /// Installs or removes software.
+    Package,
+    /// Changes system configuration, services, users or disks.
+    System,
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Risk::Low => "low",
+            Risk::Medium => "medium",
+            Risk::High => "high",
+        })
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Category::Inspect => "inspect",
+            Category::Other => "other",
+            Category::Filesystem => "filesystem",
+            Category::Process => "process",
+            Category::Network => "network",
+            Category::Package => "package",
+            Category::System => "system",
+        })
+    }
+}
+
+const INSPECT: &[&str] = &[
+    "ls", "cat", "less", "more", "head", "tail", "grep", "egrep", "rg", "ag", "find", "fd", "locate", "wc",
+    "du", "df", "stat", "file", "tree", "echo", "printf", "pwd", "whoami", "id", "date", "uname",
+    "which", "type", "man", "ps", "top", "htop", "free", "uptime", "diff", "sort", "uniq", "cut", "awk",
+    "jq", "column", "tr", "basename", "dirname", "realpath", "history", "lsof", "md5sum", "sha256sum",
+];
+const FILESYSTEM: &[&str] = &[
+    "rm", "rmdir", "mv", "cp", "mkdir", "touch", "chmod", "chown", "chgrp", "ln", "tar", "zip", "unzip",
+    "gzip", "gunzip", "bzip2", "xz", "truncate", "shred", "tee", "sed", "patch", "install", "rename",
+];
+const PROCESS: &[&str] = &["kill", "pkill", "killall", "renice", "watch"];
+const NETWORK: &[&str] = &[
+    "curl", "wget", "ssh", "scp", "sftp", "rsync", "ping", "nc", "netcat", "dig", "nslookup", "host",
+    "ftp", "telnet", "traceroute", "nmap", "http", "https",
+];
+const PACKAGE: &[&str] = &[
+    "apt", "apt-get", "dpkg", "dnf", "yum", "rpm", "pacman", "zypper", "apk", "brew", "port", "snap",
+    "flatpak", "pip", "pip3", "pipx", "npm", "yarn", "pnpm", "gem", "cargo", "go",
+];
+const SYSTEM: &[&str] = &[
+    "systemctl", "service", "launchctl", "mount", "umount", "fdisk", "parted", "wipefs", "dd", "useradd",
+    "usermod", "userdel", "groupadd", "passwd", "crontab", "sysctl", "modprobe", "iptables", "ufw",
+    "shutdown", "reboot", "halt", "poweroff", "chroot", "defaults",
+];
+/// Programs that can destroy data or make the machine unusable on their own.
+const DESTRUCTIVE: &[&str] = &["dd", "shred", "fdisk", "parted", "wipefs", "shutdown", "reboot", "halt", "poweroff"];
+/// Programs that change something but are routinely used safely.
+const MODIFYING: &[&str] = &["rm", "mv", "chmod", "chown", "chgrp", "truncate", "kill", "pkill", "killall", "crontab"];
+
+/// Estimates the risk and category of a command line from the programs it
+/// runs, their flags and any output redirection.
+pub fn assess(command: &str) -> (Risk, Category) {
+    let mut assessment = (Risk::Low, Category::Inspect);
+    match shell::parse(command) {
+        Ok(list) => assess_list(&list, &mut assessment),
+        // Make what we can of text that isn't valid shell, line by line.
+        Err(_) => {
+            for line in command.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+                match shell::parse(line) {
+                    Ok(list) => assess_list(&list, &mut assessment),
+                    Err(_) => add(&mut assessment, assess_simple(&line.split_whitespace().collect::<Vec<_>>())),
+                }
+            }
+        }
+    }
+    assessment
+}
+
+fn add(assessment: &mut (Risk, Category), (risk, category): (Risk, Category)) {
+    assessment.0 = assessment.0.max(risk);
+    assessment.1 = assessment.1.max(category);
+}
+
+fn assess_list(list: &List, assessment: &mut (Risk, Category)) {
+    for pipeline in list.pipelines() {
+        let mut downloads = false;
+        for command in &pipeline.commands {
+            for redirect in command.redirects() {
+                add(assessment, assess_redirect(redirect));
+            }
+            let Command::Simple(simple) = command else {
+                downloads = false;
+                continue;
+            };
+            let words: Vec<String> = simple.words.iter().map(Word::value).collect();
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            if words.is_empty() {
+                downloads = false;
+                continue;
+            }
+            let program = words.get(shell::wrapped_program(&words)).map(|p| p.rsplit('/').next().unwrap_or(p));
+            let (mut risk, category) = assess_simple(&words);
+            // Running a download, piped in or substituted, runs unreviewed code.
+            let fetched = simple.words.iter().flat_map(Word::substitutions).any(fetches);
+            if matches!(program, Some("sh" | "bash" | "zsh")) && (downloads || fetched) {
+                risk = Risk::High;
+            }
+            downloads = matches!(program, Some("curl" | "wget"));
+            add(assessment, (risk, category));
+        }
+    }
+}
+
+fn fetches(list: &List) -> bool {
+    list.simple_commands().iter().any(|command| matches!(command.program().as_deref(), Some("curl" | "wget")))
+}
+
+/// Output redirected to a file changes it, to a disk device destroys it.
+pub(crate) fn assess_redirect(redirect: &Redirect) -> (Risk, Category) {
+    let RedirectTarget::Word(target) = &redirect.target else {
+        return (Risk::Low, Category::Inspect);
+    };
+    let target = target.value();
+    let appends = matches!(redirect.op, RedirectOp::Append | RedirectOp::AppendOutputAndError);
+    let writes = appends
+        || matches!(redirect.op, RedirectOp::Output | RedirectOp::Clobber | RedirectOp::OutputAndError | RedirectOp::ReadWrite)
+        // `>&file` is `&>file`; `>&2` duplicates a descriptor.
+        || (redirect.op == RedirectOp::DupOutput && !target.chars().all(|c| c.is_ascii_digit() || c == '-'));
+    if !writes || target == "/dev/null" {
+        return (Risk::Low, Category::Inspect);
+    }
+    let risk = if target.starts_with("/dev/sd") || target.starts_with("/dev/nvme") {
+        Risk::High
+    } else if appends {
+        Risk::Low
+    } else {
+        Risk::Medium
+    };
+    (risk, Category::Filesystem)
+}
+
+/// Assesses a single command without operators, e.g. `sudo rm -rf build`.
+/// Wrappers such as `sudo`, `env` or `xargs` are looked through to the
+/// command they run.
+pub(crate) fn assess_simple(words: &[&str]) -> (Risk, Category) {
+    let assignments = words.iter().take_while(|w| w.contains('=') && !w.starts_with('-')).count();
+    let words = &words[assignments..];
+    let start = shell::wrapped_program(words);
+    let elevated = words[..start].iter().any(|w| matches!(w.rsplit('/').next(), Some("sudo" | "doas")));
+    let Some(program) = words.get(start) else {
+        return (Risk::Low, Category::Inspect);
+    };
+    let program = program.rsplit('/').next().unwrap_or(program);
+    let args: Vec<&str> = words[start + 1..].to_vec();
+    let has_flag = |short: char, long: &str| {
+        args.iter().any(|a| a == &long || (a.starts_with('-') && !a.starts_with("--") && a.contains(short)))
+    };
+
+    let category = if program == "git" {
+        match args.first().copied() {
+            Some("clone" | "fetch" | "pull" | "push") => Category::Network,
+            Some("status" | "log" | "diff" | "show" | "blame") | None => Category::Inspect,
+            _ => Category::Filesystem,
+        }
+    } else if program == "find" && args.iter().any(|a| matches!(*a, "-delete" | "-exec" | "-execdir")) {
+        Category::Filesystem
+    } else if program == "sed" && !has_flag('i', "--in-place") {
+        Category::Inspect
+    } else if SYSTEM.contains(&program) || program.starts_with("mkfs") {
+        Category::System
+    } else if PACKAGE.contains(&program) {
+        Category::Package
+    } else if NETWORK.contains(&program) {
+        Category::Network
+    } else if PROCESS.contains(&program) {
+        Category::Process
+    } else if FILESYSTEM.contains(&program) {
+        Category::Filesystem
+    } else if INSPECT.contains(&program) {
+        Category::Inspect
+    } else {
+        Category::Other
+    };
+
+    let destructive = DESTRUCTIVE.contains(&program)
+        || program.starts_with("mkfs")
+        || (program == "rm" && has_flag('r', "--recursive") && has_flag('f', "--force"))
+        || (matches!(program, "chmod" | "chown" | "chgrp") && has_flag('R', "--recursive"))
+        || (program == "git" && args.iter().any(|a| matches!(*a, "--force" | "-f" | "--hard" | "clean")));
+
+    let mut risk = if destructive {
+        Risk::High
+    } else if MODIFYING.contains(&program)
+        || matches!(category, Category::Package | Category::System)
+        || (program == "find" && category == Category::Filesystem)
+    {
+        Risk::Medium
+    } else {
+        Risk::Low
+    };
+    if elevated {
+        risk = risk.max(Risk::Medium);
+    }
+    (risk, category)
+}
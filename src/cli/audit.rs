@@ -0,0 +1,66 @@
+use anyhow::{Result, anyhow};
+use clap::{Subcommand, ValueEnum};
+use std::path::Path;
+use std::process::ExitStatus;
+use toli::audit::{self, AuditLog, AuditRecord};
+use toli::config::Config;
+use toli::LLMBackend;
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// Check that no record was changed, removed, inserted or reordered
+    Verify,
+    /// Print every record
+    Export {
+        #[arg(long = "format", value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+pub async fn run(action: AuditAction, config_path: &Path) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let log = AuditLog::from_config(&config.audit)?;
+    match action {
+        AuditAction::Verify => {
+            let count = log
+                .verify()
+                .map_err(|e| anyhow!("Audit log {} failed verification: {}", log.path().display(), e))?;
+            println!("Audit log {}: {} record{}, chain intact.", log.path().display(), count, if count == 1 { "" } else { "s" });
+        }
+        AuditAction::Export { format } => {
+            let records = log.records()?;
+            match format {
+                Format::Csv => print!("{}", audit::to_csv(&records)),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&records)?),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The audit log when `[audit]` is enabled, checked to be writable before
+/// anything runs.
+pub fn open(config: &Config) -> Result<Option<AuditLog>> {
+    if !config.audit.enabled {
+        return Ok(None);
+    }
+    let log = AuditLog::from_config(&config.audit)?;
+    log.check()?;
+    Ok(Some(log))
+}
+
+/// Logs `command`, run for `query`, with the replies `llm` gave. Failures are
+/// reported, since the command has already run.
+pub fn record(log: Option<&AuditLog>, query: &str, command: &str, llm: &dyn LLMBackend, status: &std::io::Result<ExitStatus>) {
+    let Some(log) = log else { return };
+    let exit_status = status.as_ref().ok().and_then(ExitStatus::code);
+    if let Err(e) = log.append(AuditRecord::new(query, command, &llm.replies(), exit_status)) {
+        eprintln!("Warning: {}", e);
+    }
+}
@@ -0,0 +1,387 @@
+use anyhow::{Result, anyhow};
+use clap::Subcommand;
+use std::path::Path;
+use std::process::Command;
+use toml::Value;
+use toli::config::{Config, LlmBackend, OllamaConfig, OpenAIConfig};
+use toli::mock::MockBackend;
+use toli::ollama::{self, OllamaBackend};
+use toli::openai::OpenAIBackend;
+use toli::prompts::Prompts;
+use toli::secret::{self, Secret};
+use super::{ask, confirm};
+
+/// Keys whose values are masked by `toli config show` and `toli config get`.
+const SECRET_KEYS: &[&str] = &["api_key"];
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Interactively create a configuration file
+    Init,
+    /// Print the configuration with secrets masked
+    Show,
+    /// Print a single value, e.g. `ollama.model`, with secrets masked
+    Get {
+        #[arg(value_name = "KEY")]
+        key: String,
+        /// Print secrets such as `openai.api_key` in full
+        #[arg(long = "reveal")]
+        reveal: bool,
+    },
+    /// Set a single value, e.g. `ollama.model llama3.2`
+    Set {
+        #[arg(value_name = "KEY")]
+        key: String,
+        #[arg(value_name = "VALUE", allow_hyphen_values = true)]
+        value: String,
+    },
+    /// Check the configuration and test connectivity to the backends
+    Validate,
+    /// Open the configuration file in $VISUAL or $EDITOR
+    Edit,
+    /// Print the path of the configuration file
+    Path,
+    /// List prompt templates and where they are loaded from
+    Prompts {
+        /// Profile whose overrides to include
+        #[arg(short = 'p', long = "profile", value_name = "NAME")]
+        profile: Option<String>,
+        /// Copy the built-in templates into the prompts directory for editing
+        #[arg(long = "export")]
+        export: bool,
+    },
+}
+
+pub async fn run(action: ConfigAction, config_path: &Path) -> Result<()> {
+    match action {
+        ConfigAction::Init => init(config_path).await,
+        ConfigAction::Show => show(config_path),
+        ConfigAction::Get { key, reveal } => get(config_path, &key, reveal),
+        ConfigAction::Set { key, value } => set(config_path, &key, &value),
+        ConfigAction::Validate => validate(config_path).await,
+        ConfigAction::Edit => edit(config_path),
+        ConfigAction::Path => {
+            println!("{}", config_path.display());
+            Ok(())
+        }
+        ConfigAction::Prompts { profile, export } => prompts(config_path, profile.as_deref(), export),
+    }
+}
+
+async fn init(config_path: &Path) -> Result<()> {
+    if config_path.exists() && !confirm(&format!("{} already exists. Overwrite it?", config_path.display()), false)? {
+        println!("Keeping the existing configuration.");
+        return Ok(());
+    }
+
+    let defaults = Config::default_for_environment();
+    let backend = loop {
+        match ask("Backend (ollama/openai)", "ollama")?.to_lowercase().as_str() {
+            "ollama" => break LlmBackend::Ollama,
+            "openai" => break LlmBackend::OpenAI,
+            other => println!("Unknown backend '{}'. Please enter 'ollama' or 'openai'.", other),
+        }
+    };
+
+    let mut new_config = Config { backend: backend.into(), ollama: None, ..defaults };
+    match backend {
+        LlmBackend::Ollama => {
+            let endpoint = ask("Ollama endpoint", "http://localhost:11434")?;
+            let model = match OllamaBackend::list_local_models(&endpoint).await {
+                Ok(models) if !models.is_empty() => {
+                    println!("\nModels available on {}:", endpoint);
+                    for (i, model) in models.iter().enumerate() {
+                        println!("  {}) {}", i + 1, model);
+                    }
+                    let choice = ask("Select a model by number or name", "1")?;
+                    match choice.parse::<usize>() {
+                        Ok(n) if n >= 1 && n <= models.len() => models[n - 1].clone(),
+                        _ => choice,
+                    }
+                }
+                Ok(_) => {
+                    println!("Ollama is running but has no models yet. Pull one with 'toli models pull <model>'.");
+                    ask("Model", "llama3.2")?
+                }
+                Err(e) => {
+                    println!("Could not reach Ollama: {}", e);
+                    ask("Model", "llama3.2")?
+                }
+            };
+            new_config.ollama = Some(OllamaConfig { endpoint, model, ..OllamaConfig::default() });
+        }
+        LlmBackend::OpenAI => {
+            println!("The API key can be stored in the config, or read from a command (e.g. 'pass show openai'),");
+            println!("an environment variable or a file when toli runs.");
+            let mut openai = OpenAIConfig::default();
+            loop {
+                match ask("Key source (paste/cmd/env/file)", "env")?.to_lowercase().as_str() {
+                    "paste" => openai.api_key = Secret::new(ask("OpenAI API key", "")?),
+                    "cmd" => openai.api_key_cmd = Some(ask("Command that prints the key", "pass show openai")?),
+                    "env" => openai.api_key_env = Some(ask("Environment variable", "OPENAI_API_KEY")?),
+                    "file" => openai.api_key_file = Some(ask("Key file", "~/.config/toli/openai.key")?.into()),
+                    other => {
+                        println!("Unknown key source '{}'.", other);
+                        continue;
+                    }
+                }
+                break;
+            }
+            openai.model = ask("Model", &openai.model)?;
+            openai.endpoint = ask("API endpoint", &openai.endpoint)?;
+            new_config.openai = Some(openai);
+        }
+        // Only offered for tests, not by the wizard.
+        LlmBackend::Mock => {}
+    }
+    new_config.additional_context = ask("Describe your environment", &new_config.additional_context)?;
+
+    new_config.save(config_path)?;
+    println!("\nWrote {}", config_path.display());
+    Ok(())
+}
+
+fn show(config_path: &Path) -> Result<()> {
+    let mut table = read_table(config_path)?;
+    mask_secrets(&mut table);
+    print!("{}", toml::to_string_pretty(&table)?);
+    Ok(())
+}
+
+fn get(config_path: &Path, key: &str, reveal: bool) -> Result<()> {
+    // Read the loaded configuration so that keys left at their defaults have values too.
+    read_table(config_path)?;
+    let Value::Table(mut table) = Value::try_from(Config::load(config_path)?)? else {
+        return Err(anyhow!("The configuration is not a table"));
+    };
+    if !reveal {
+        mask_secrets(&mut table);
+    }
+    let mut current = &Value::Table(table);
+    for part in key.split('.') {
+        current = current
+            .get(part)
+            .ok_or_else(|| anyhow!("Key '{}' is not set", key))?;
+    }
+    match current {
+        Value::String(s) => println!("{}", s),
+        Value::Table(t) => print!("{}", toml::to_string_pretty(t)?),
+        other => println!("{}", other),
+    }
+    Ok(())
+}
+
+fn set(config_path: &Path, key: &str, value: &str) -> Result<()> {
+    let table = read_table(config_path)?;
+    // A value that reads as a number, bool or array but goes in a string
+    // field, like a model named `3`, is stored as the text it was given.
+    let mut values = vec![parse_value(value)];
+    if !values[0].is_str() {
+        values.push(Value::String(value.to_string()));
+    }
+
+    let mut first_error = None;
+    for value in values {
+        let mut table = table.clone();
+        insert(&mut table, key, value)?;
+        // Refuse to write a file that would no longer load.
+        let checked = Value::Table(table.clone())
+            .try_into::<Config>()
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate());
+        match checked {
+            Ok(()) => return secret::write_private(config_path, &toml::to_string_pretty(&table)?),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    let error = first_error.map(|e| e.to_string()).unwrap_or_default();
+    Err(anyhow!("Setting '{}' would make the configuration invalid: {}", key, error.trim_end()))
+}
+
+fn insert(table: &mut toml::Table, key: &str, value: Value) -> Result<()> {
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, parents) = parts.split_last().ok_or_else(|| anyhow!("Key must not be empty"))?;
+
+    let mut current = table;
+    for part in parents {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Table(toml::Table::new()));
+        current = entry
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("'{}' is not a table", part))?;
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+async fn validate(config_path: &Path) -> Result<()> {
+    if !config_path.exists() {
+        return Err(anyhow!("No configuration found at {}. Run 'toli config init' to create one.", config_path.display()));
+    }
+    // Loading (rather than just parsing) upgrades an old file before it is checked.
+    let config = Config::load(config_path)?;
+    println!("Schema: ok ({}, config version {})", config_path.display(), config.config_version);
+
+    let mut failures = 0;
+    if let Some(name) = &config.default_profile {
+        if !config.profiles.contains_key(name) {
+            println!("Profiles: default_profile '{}' is not defined", name);
+            failures += 1;
+        }
+    }
+
+    // Sections that are left out fall back to their defaults when selected.
+    let ollama = config.ollama.clone()
+        .or_else(|| config.backend.contains(LlmBackend::Ollama).then(OllamaConfig::default));
+    let openai = config.openai.clone()
+        .or_else(|| config.backend.contains(LlmBackend::OpenAI).then(OpenAIConfig::default));
+
+    if let Some(ollama) = &ollama {
+        match OllamaBackend::list_local_models(&ollama.endpoint).await {
+            Ok(models) if models.iter().any(|m| ollama::model_matches(m, &ollama.model)) => {
+                println!("Ollama: ok ({} at {})", ollama.model, ollama.endpoint);
+            }
+            Ok(_) => {
+                println!("Ollama: model '{}' is not available at {} (try 'toli models pull {}')", ollama.model, ollama.endpoint, ollama.model);
+                failures += 1;
+            }
+            Err(e) => {
+                println!("Ollama: {}", e);
+                failures += 1;
+            }
+        }
+    }
+
+    if let Some(openai) = &openai {
+        let check = match openai.resolve_api_key() {
+            Ok(api_key) => {
+                let backend = OpenAIBackend::new(api_key, Some(openai.endpoint.clone()), Some(openai.model.clone()));
+                backend.check_connection().await
+            }
+            Err(e) => Err(e),
+        };
+        match check {
+            Ok(()) => println!("OpenAI: ok ({} at {})", openai.model, openai.endpoint),
+            Err(e) => {
+                println!("OpenAI: {}", e);
+                failures += 1;
+            }
+        }
+    }
+
+    if config.backend.contains(LlmBackend::Mock) {
+        match &config.mock {
+            Some(mock) => match MockBackend::from_file(&mock.fixtures) {
+                Ok(_) => println!("Mock: ok ({})", mock.fixtures.display()),
+                Err(e) => {
+                    println!("Mock: {}", e);
+                    failures += 1;
+                }
+            },
+            None => {
+                println!("Mock: backend is selected but the [mock] section is missing");
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(anyhow!("Configuration has {} problem(s)", failures));
+    }
+    Ok(())
+}
+
+fn edit(config_path: &Path) -> Result<()> {
+    if !config_path.exists() {
+        Config::load(config_path)?;
+    }
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut args = shell_words::split(&editor)?;
+    if args.is_empty() {
+        return Err(anyhow!("$EDITOR is empty"));
+    }
+    let program = args.remove(0);
+    let status = Command::new(&program)
+        .args(&args)
+        .arg(config_path)
+        .status()
+        .map_err(|e| anyhow!("Failed to launch editor '{}': {}", program, e))?;
+    if !status.success() {
+        return Err(anyhow!("Editor exited with status: {}", status));
+    }
+
+    let config_str = std::fs::read_to_string(config_path)?;
+    if let Err(e) = Config::parse(&config_str, config_path) {
+        println!("Warning: {}", e);
+    }
+    Ok(())
+}
+
+fn prompts(config_path: &Path, profile: Option<&str>, export: bool) -> Result<()> {
+    let prompts_dir = config_path.parent().map(|dir| dir.join("prompts")).unwrap_or_else(|| "prompts".into());
+    if export {
+        let written = Prompts::export_builtin(&prompts_dir)?;
+        for path in &written {
+            println!("Wrote {}", path.display());
+        }
+        if written.is_empty() {
+            println!("All templates already exist in {}", prompts_dir.display());
+        }
+        // Exported copies are identical to the built-ins, so listing them adds nothing.
+        return Ok(());
+    }
+
+    let prompts = Prompts::load(&prompts_dir, profile)?;
+    println!("Prompt version: {}", prompts.version());
+    println!("Overrides are read from {}\n", prompts_dir.display());
+    for (name, source) in prompts.sources() {
+        match source {
+            Some(path) => println!("  {:<18} {}", name, path.display()),
+            None => println!("  {:<18} built-in", name),
+        }
+    }
+    Ok(())
+}
+
+fn read_table(config_path: &Path) -> Result<toml::Table> {
+    if !config_path.exists() {
+        return Err(anyhow!("No configuration found at {}. Run 'toli config init' to create one.", config_path.display()));
+    }
+    let config_str = std::fs::read_to_string(config_path)?;
+    Ok(toml::from_str(&config_str)?)
+}
+
+fn mask_secrets(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        match value {
+            Value::Table(inner) => mask_secrets(inner),
+            Value::String(s) if SECRET_KEYS.contains(&key.as_str()) => *s = mask_secret(s),
+            _ => {}
+        }
+    }
+}
+
+/// Keeps just enough of a secret to recognise it, e.g. `sk-…a1b2`.
+fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return String::from("********");
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+/// Interprets `value` as a TOML literal (number, bool, array) and falls back to a plain string;
+/// `set` retries a literal that doesn't fit its field as a string.
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<toml::Table>(&format!("v = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
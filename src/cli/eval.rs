@@ -0,0 +1,303 @@
+use anyhow::{Result, anyhow};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use toli::config::Config;
+use toli::llm::{self, ResponseType};
+use toli::normalize;
+use toli::sandbox::{self, Isolation};
+
+/// How long a command may run during `--execute` before it is killed.
+const EXECUTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args)]
+pub struct EvalArgs {
+    /// JSON Lines file with one labelled query per line
+    #[arg(value_name = "DATASET")]
+    dataset: PathBuf,
+
+    /// Profiles to evaluate; may be repeated. Defaults to the active configuration
+    #[arg(short = 'p', long = "profile", value_name = "NAME")]
+    profiles: Vec<String>,
+
+    /// Also run suggestions in a sandbox on a scratch copy of each entry's fixture directory
+    /// and count them as correct when they produce the same result as the expected command
+    #[arg(long = "execute")]
+    execute: bool,
+
+    /// Print the report as JSON
+    #[arg(long = "json")]
+    json: bool,
+}
+
+/// One line of the dataset, e.g.
+/// `{"query": "count lines in main.rs", "expected": ["wc -l main.rs", "wc -l < main.rs"], "fixture": "fixtures/src"}`.
+#[derive(Debug, Deserialize)]
+struct Case {
+    query: String,
+    expected: Expected,
+    /// Directory (relative to the dataset) that commands are run in with `--execute`.
+    #[serde(default)]
+    fixture: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Expected {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Expected {
+    fn commands(&self) -> &[String] {
+        match self {
+            Expected::One(command) => std::slice::from_ref(command),
+            Expected::Many(commands) => commands,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ModelReport {
+    model: String,
+    prompt_version: String,
+    cases: usize,
+    /// The first suggestion matched an expected command.
+    top1_correct: usize,
+    /// Any suggestion matched an expected command.
+    any_correct: usize,
+    /// Correct only because execution produced the same result.
+    correct_by_execution: usize,
+    parse_failures: usize,
+    errors: usize,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    latencies_ms: Vec<u128>,
+}
+
+impl ModelReport {
+    fn rate(&self, count: usize) -> f64 {
+        if self.cases == 0 { 0.0 } else { count as f64 / self.cases as f64 }
+    }
+
+    fn latency_percentile(&self, percentile: f64) -> u128 {
+        if self.latencies_ms.is_empty() {
+            return 0;
+        }
+        let mut sorted = self.latencies_ms.clone();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+        sorted[index]
+    }
+}
+
+pub async fn run(args: EvalArgs, config_path: &Path) -> Result<()> {
+    if args.execute && Isolation::detect().is_none() {
+        return Err(anyhow!("--execute runs commands in a sandbox, which needs bubblewrap (bwrap) or unshare on Linux, and neither was found"));
+    }
+    let cases = load_dataset(&args.dataset)?;
+    let dataset_dir = args.dataset.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let profiles: Vec<Option<String>> = if args.profiles.is_empty() {
+        vec![None]
+    } else {
+        args.profiles.iter().cloned().map(Some).collect()
+    };
+
+    let mut reports = Vec::new();
+    for profile in &profiles {
+        let mut config = Config::load(config_path)?;
+        config.apply_profile(profile.as_deref())?;
+        let backend = llm::from_config(&config)?;
+        let mut report = ModelReport {
+            model: match profile {
+                Some(name) => format!("{} ({})", config.model_label(), name),
+                None => config.model_label(),
+            },
+            prompt_version: config.prompts()?.version().to_string(),
+            ..Default::default()
+        };
+
+        for (i, case) in cases.iter().enumerate() {
+            eprint!("\r{}: {}/{}", report.model, i + 1, cases.len());
+            std::io::stderr().flush().ok();
+            report.cases += 1;
+
+            let started = Instant::now();
+            let result = backend.translate_to_command(&case.query, &config.additional_context).await;
+            report.latencies_ms.push(started.elapsed().as_millis());
+
+            let options = match result {
+                Ok(options) => options,
+                Err(_) => {
+                    report.errors += 1;
+                    continue;
+                }
+            };
+            if options.iter().all(ResponseType::is_parse_failure) {
+                report.parse_failures += 1;
+                continue;
+            }
+
+            let suggestions: Vec<&str> = options.iter().filter_map(ResponseType::command).collect();
+            let matches = |command: &str| case.expected.commands().iter().any(|e| normalize::equivalent(command, e));
+            if suggestions.first().is_some_and(|c| matches(c)) {
+                report.top1_correct += 1;
+                report.any_correct += 1;
+            } else if suggestions.iter().any(|c| matches(c)) {
+                report.any_correct += 1;
+            } else if args.execute {
+                if let Some(fixture) = &case.fixture {
+                    let fixture = dataset_dir.join(fixture);
+                    if let Some(top) = suggestions.first() {
+                        if same_effect(top, &case.expected.commands()[0], &fixture)? {
+                            report.top1_correct += 1;
+                            report.any_correct += 1;
+                            report.correct_by_execution += 1;
+                        }
+                    }
+                }
+            }
+        }
+        eprintln!();
+        let usage = backend.usage();
+        report.prompt_tokens = usage.iter().map(|record| record.usage.prompt_tokens).sum();
+        report.completion_tokens = usage.iter().map(|record| record.usage.completion_tokens).sum();
+        super::usage::record(&usage, &config.usage, false);
+        reports.push(report);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        print_table(&reports);
+    }
+    Ok(())
+}
+
+fn load_dataset(path: &Path) -> Result<Vec<Case>> {
+    let dataset = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read dataset {}: {}", path.display(), e))?;
+    let cases = dataset
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow!("{}:{}: invalid dataset entry: {}", path.display(), i + 1, e))
+        })
+        .collect::<Result<Vec<Case>>>()?;
+    if cases.is_empty() {
+        return Err(anyhow!("Dataset {} is empty", path.display()));
+    }
+    Ok(cases)
+}
+
+fn print_table(reports: &[ModelReport]) {
+    println!(
+        "{:<32} {:<20} {:>6} {:>7} {:>7} {:>9} {:>7} {:>9} {:>9}",
+        "model", "prompts", "cases", "top-1", "any", "parse-err", "errors", "p50 ms", "p95 ms"
+    );
+    for report in reports {
+        println!(
+            "{:<32} {:<20} {:>6} {:>6.1}% {:>6.1}% {:>8.1}% {:>7} {:>9} {:>9}",
+            report.model,
+            report.prompt_version,
+            report.cases,
+            report.rate(report.top1_correct) * 100.0,
+            report.rate(report.any_correct) * 100.0,
+            report.rate(report.parse_failures) * 100.0,
+            report.errors,
+            report.latency_percentile(0.5),
+            report.latency_percentile(0.95),
+        );
+    }
+}
+
+/// Runs both commands in separate scratch copies of `fixture` and compares
+/// exit status, output (ignoring line order) and the resulting files.
+fn same_effect(suggested: &str, expected: &str, fixture: &Path) -> Result<bool> {
+    let suggested = execute_in_copy(suggested, fixture)?;
+    let expected = execute_in_copy(expected, fixture)?;
+    Ok(suggested.is_some() && suggested == expected)
+}
+
+#[derive(PartialEq)]
+struct Outcome {
+    status: Option<i32>,
+    stdout_lines: Vec<String>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// Runs `command` in a sandbox on a scratch copy of `fixture`, with a clean
+/// environment and `HOME` pointed at the copy. Returns `None` when the
+/// command timed out.
+fn execute_in_copy(command: &str, fixture: &Path) -> Result<Option<Outcome>> {
+    let scratch = std::env::temp_dir().join(format!("toli-eval-{}-{}", std::process::id(), unique_suffix()));
+    copy_dir(fixture, &scratch)?;
+    let outcome = execute_in(command, &scratch.canonicalize()?);
+    std::fs::remove_dir_all(&scratch).ok();
+    outcome
+}
+
+fn execute_in(command: &str, scratch: &Path) -> Result<Option<Outcome>> {
+    let args = [
+        String::from("env"),
+        String::from("-i"),
+        format!("PATH={}", std::env::var("PATH").unwrap_or_default()),
+        format!("HOME={}", scratch.display()),
+        String::from("sh"),
+        String::from("-c"),
+        command.to_string(),
+    ];
+    let execution = sandbox::execute(&args, scratch, EXECUTION_TIMEOUT)?;
+    if execution.timed_out {
+        return Ok(None);
+    }
+    let mut stdout_lines: Vec<String> = execution.stdout.lines().map(|l| l.trim_start_matches("./").to_string()).collect();
+    stdout_lines.sort();
+    let mut files = BTreeMap::new();
+    snapshot(scratch, scratch, &mut files)?;
+    Ok(Some(Outcome { status: execution.status, stdout_lines, files }))
+}
+
+fn unique_suffix() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)
+        .map_err(|e| anyhow!("Failed to read fixture {}: {}", from.display(), e))?
+    {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn snapshot(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        if entry.file_type()?.is_dir() {
+            files.insert(relative, Vec::new());
+            snapshot(root, &path, files)?;
+        } else {
+            files.insert(relative, std::fs::read(&path).unwrap_or_default());
+        }
+    }
+    Ok(())
+}
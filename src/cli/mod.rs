@@ -0,0 +1,34 @@
+//! Subcommands of the `toli` binary. Everything reusable lives in the library.
+
+use anyhow::Result;
+use std::io::{self, Write};
+
+pub mod audit;
+pub mod config;
+pub mod eval;
+pub mod models;
+pub mod undo;
+pub mod usage;
+
+/// Asks a question on stdout, returning `default` when the answer is empty.
+pub fn ask(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    Ok(if input.is_empty() { default.to_string() } else { input.to_string() })
+}
+
+/// Asks a yes/no question; an empty answer means `default`.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    let answer = ask(&format!("{} [{}]", question, if default { "Y/n" } else { "y/N" }), "")?;
+    Ok(match answer.to_lowercase().as_str() {
+        "" => default,
+        a => a.starts_with('y'),
+    })
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use clap::Subcommand;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::Path;
+use toli::config::Config;
+use toli::ollama::{self, ModelNotFound, OllamaBackend, PullProgress};
+
+const BAR_WIDTH: usize = 30;
+
+#[derive(Subcommand)]
+pub enum ModelsAction {
+    /// List the models installed in Ollama (the default)
+    List,
+    /// Download a model into Ollama
+    Pull {
+        /// Model name, e.g. llama3.2 or qwen2.5-coder:7b
+        model: String,
+    },
+}
+
+pub async fn run(action: Option<ModelsAction>, config_path: &Path) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let ollama = config.ollama.clone().unwrap_or_default();
+    match action.unwrap_or(ModelsAction::List) {
+        ModelsAction::List => list(&ollama.endpoint, &ollama.model).await,
+        ModelsAction::Pull { model } => pull(&ollama.endpoint, &model).await,
+    }
+}
+
+async fn list(endpoint: &str, configured: &str) -> Result<()> {
+    let models = OllamaBackend::local_models(endpoint).await?;
+    if models.is_empty() {
+        println!("No models installed in Ollama at {}. Pull one with 'toli models pull <model>'.", endpoint);
+        return Ok(());
+    }
+
+    println!("{:<32} {:>9} {:>8} {:>9}", "NAME", "SIZE", "PARAMS", "CONTEXT");
+    for model in &models {
+        let details = OllamaBackend::show_model(endpoint, &model.name).await.ok().flatten().unwrap_or_default();
+        println!(
+            "{:<32} {:>9} {:>8} {:>9}{}",
+            model.name,
+            format_size(model.size),
+            details.parameter_size.as_deref().unwrap_or("-"),
+            details.context_length.map(|n| n.to_string()).as_deref().unwrap_or("-"),
+            if ollama::model_matches(&model.name, configured) { "  (configured)" } else { "" }
+        );
+    }
+    if !models.iter().any(|m| ollama::model_matches(&m.name, configured)) {
+        println!("\nThe configured model '{}' is not installed. Pull it with 'toli models pull {}'.", configured, configured);
+    }
+    Ok(())
+}
+
+async fn pull(endpoint: &str, model: &str) -> Result<()> {
+    OllamaBackend::pull_model(endpoint, model, show_progress).await?;
+    eprintln!();
+    println!("Pulled {}", model);
+    Ok(())
+}
+
+/// Runs `request`; when it fails because the Ollama model hasn't been pulled,
+/// offers to pull it and then runs `request` once more.
+pub async fn retry_after_pull<T, F, Fut>(request: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let error = match request().await {
+        Err(e) if e.is::<ModelNotFound>() => e,
+        result => return result,
+    };
+    let missing = error.downcast_ref::<ModelNotFound>().expect("checked above");
+
+    print!("Model '{}' is not available in Ollama. Pull it now? [Y/n]: ", missing.model);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    // No answer at all (e.g. stdin is not a terminal) means no.
+    if io::stdin().read_line(&mut input)? == 0 || input.trim().to_lowercase().starts_with('n') {
+        println!();
+        return Err(error);
+    }
+
+    pull(&missing.endpoint, &missing.model).await?;
+    request().await
+}
+
+fn show_progress(progress: &PullProgress) {
+    let status: String = progress.status.chars().take(24).collect();
+    if progress.total > 0 {
+        let completed = progress.completed.min(progress.total);
+        let percent = completed * 100 / progress.total;
+        let filled = percent as usize * BAR_WIDTH / 100;
+        eprint!(
+            "\r{:<24} [{}{}] {:>3}% {:>9} / {:<9}",
+            status,
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            percent,
+            format_size(completed),
+            format_size(progress.total)
+        );
+    } else {
+        eprint!("\r{:<80}", status);
+    }
+    io::stderr().flush().ok();
+}
+
+/// Formats a byte count the way Ollama does, e.g. `2.0 GB`.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1_000_000_000 => format!("{:.1} GB", b as f64 / 1e9),
+        b if b >= 1_000_000 => format!("{:.0} MB", b as f64 / 1e6),
+        b if b >= 1_000 => format!("{:.0} KB", b as f64 / 1e3),
+        b => format!("{} B", b),
+    }
+}
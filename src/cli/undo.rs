@@ -0,0 +1,39 @@
+use anyhow::Result;
+use toli::undo::Store;
+use toli::usage;
+
+/// Shows the last snapshot and restores it once confirmed.
+pub fn run() -> Result<()> {
+    let store = Store::open()?;
+    let Some(snapshot) = store.latest()? else {
+        println!("Nothing to undo.");
+        return Ok(());
+    };
+
+    println!(
+        "Before '{}' ran in {} on {}:",
+        snapshot.command,
+        snapshot.cwd.display(),
+        usage::date(snapshot.timestamp)
+    );
+    let steps = snapshot.steps()?;
+    if steps.is_empty() {
+        println!("  (nothing has changed since)");
+    }
+    for step in &steps {
+        let path = if step.path.as_os_str().is_empty() { String::from(".") } else { step.path.display().to_string() };
+        if step.existed {
+            println!("  restore  {}", path);
+        } else {
+            println!("  remove   {}", path);
+        }
+    }
+    if !steps.is_empty() && !super::confirm("Restore these paths?", false)? {
+        println!("Nothing restored.");
+        return Ok(());
+    }
+    let count = steps.len();
+    snapshot.restore()?;
+    println!("Restored {} path{}.", count, if count == 1 { "" } else { "s" });
+    Ok(())
+}
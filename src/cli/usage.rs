@@ -0,0 +1,153 @@
+use anyhow::{Result, anyhow};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::Path;
+use toli::config::{Config, LlmBackend};
+use toli::usage::{self, Ledger, Totals, UsageConfig, UsageRecord};
+
+#[derive(Args)]
+pub struct UsageArgs {
+    /// Number of days shown in the daily totals
+    #[arg(long = "days", value_name = "N", default_value_t = 7)]
+    days: u64,
+}
+
+pub async fn run(args: UsageArgs, config_path: &Path) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let ledger = Ledger::from_config(&config.usage)?;
+    let records = ledger.records()?;
+    if records.is_empty() {
+        println!("No usage recorded yet in {}.", ledger.path().display());
+        return Ok(());
+    }
+
+    let now = usage::now();
+    let since = usage::date(now.saturating_sub(args.days.saturating_sub(1) * 86_400));
+    let this_month = usage::date(now)[..7].to_string();
+
+    let mut daily: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut monthly: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut by_model: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in &records {
+        let date = usage::date(record.timestamp);
+        if date >= since {
+            daily.entry(date.clone()).or_default().add(record, &config.usage);
+        }
+        if date.starts_with(&this_month) {
+            by_model
+                .entry(format!("{}/{}", record.backend, record.model))
+                .or_default()
+                .add(record, &config.usage);
+        }
+        monthly.entry(date[..7].to_string()).or_default().add(record, &config.usage);
+    }
+
+    print_totals(&format!("Daily (last {} days)", args.days), "DATE", &daily);
+    print_totals("Monthly", "MONTH", &monthly);
+    print_totals(&format!("By model ({})", this_month), "MODEL", &by_model);
+
+    if monthly.values().any(|totals| totals.unpriced > 0) {
+        println!("\n* Includes requests to models without a price; add them under [usage.prices] in the config.");
+    }
+    if let Some(budget) = config.usage.monthly_budget {
+        let spent = monthly.get(&this_month).map_or(0.0, |totals| totals.cost);
+        println!("\nBudget: ${:.2} of ${:.2} used this month", spent, budget);
+    }
+    Ok(())
+}
+
+fn print_totals(title: &str, label: &str, rows: &BTreeMap<String, Totals>) {
+    println!("\n{}", title);
+    if rows.is_empty() {
+        println!("  (none)");
+        return;
+    }
+    println!("{:<32} {:>8} {:>12} {:>12} {:>10}", label, "REQUESTS", "PROMPT", "COMPLETION", "COST");
+    for (key, totals) in rows {
+        println!(
+            "{:<32} {:>8} {:>12} {:>12} {:>10}",
+            key,
+            totals.requests,
+            totals.prompt_tokens,
+            totals.completion_tokens,
+            format!("${:.4}{}", totals.cost, if totals.unpriced > 0 { "*" } else { "" })
+        );
+    }
+}
+
+/// Refuses to continue when hosted backends are in use and this month's
+/// estimated spend has reached `usage.monthly_budget`.
+pub fn check_budget(config: &Config, ensemble: bool) -> Result<()> {
+    let Some(budget) = config.usage.monthly_budget else {
+        return Ok(());
+    };
+    if !uses_hosted_backend(config, ensemble) {
+        return Ok(());
+    }
+    let records = Ledger::from_config(&config.usage)?.records()?;
+    let spent = usage::spent_in_month(&records, &config.usage, usage::now());
+    if spent >= budget {
+        return Err(anyhow!(
+            "Monthly budget of ${:.2} reached (${:.2} spent this month); raise usage.monthly_budget to continue",
+            budget,
+            spent
+        ));
+    }
+    Ok(())
+}
+
+fn uses_hosted_backend(config: &Config, ensemble: bool) -> bool {
+    if ensemble && !config.ensemble.is_empty() {
+        return config.ensemble.iter().any(|name| {
+            match config.profiles.get(name).and_then(|profile| profile.backend.as_ref()) {
+                Some(backend) => backend.contains(LlmBackend::OpenAI),
+                None => config.backend.contains(LlmBackend::OpenAI),
+            }
+        });
+    }
+    config.backend.contains(LlmBackend::OpenAI)
+}
+
+/// Appends the usage of a run to the ledger and, with `verbose`, prints it.
+pub fn record(records: &[UsageRecord], config: &UsageConfig, verbose: bool) {
+    if verbose {
+        for record in records {
+            eprintln!("{}", describe(record, config));
+        }
+    }
+    // Failing to keep the books shouldn't fail the command.
+    if let Err(e) = Ledger::from_config(config).and_then(|ledger| ledger.append(records)) {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+fn describe(record: &UsageRecord, config: &UsageConfig) -> String {
+    let mut line = format!(
+        "Usage: {}/{} {}{}: {} prompt + {} completion tokens in {:.2}s",
+        record.backend,
+        record.model,
+        record.task,
+        if record.prompt_version.is_empty() { String::new() } else { format!(" (prompts {})", record.prompt_version) },
+        record.usage.prompt_tokens,
+        record.usage.completion_tokens,
+        record.elapsed_ms as f64 / 1000.0
+    );
+    let timings: Vec<String> = [
+        ("model load", record.usage.load_ms.filter(|&ms| ms > 0)),
+        ("prompt eval", record.usage.prompt_eval_ms),
+        ("eval", record.usage.eval_ms),
+        ("total", record.usage.total_ms),
+    ]
+    .iter()
+    .filter_map(|(name, ms)| ms.map(|ms| format!("{} {:.2}s", name, ms as f64 / 1000.0)))
+    .collect();
+    if !timings.is_empty() {
+        line.push_str(&format!(" ({})", timings.join(", ")));
+    }
+    match config.cost(record) {
+        Some(cost) if cost > 0.0 => line.push_str(&format!(", ~${:.4}", cost)),
+        Some(_) => {}
+        None => line.push_str(", no price configured"),
+    }
+    line
+}
@@ -1,68 +1,513 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::audit::AuditConfig;
+use crate::classify::Thresholds;
+use crate::ollama::{KeepAlive, OllamaApi, OllamaOptions};
+use crate::prompts::Prompts;
+use crate::redact::RedactConfig;
+use crate::secret::{self, Secret};
+use crate::usage::UsageConfig;
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The schema version written by this release. Bump it together with a new
+/// entry in `MIGRATIONS` whenever existing files need to be rewritten.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` file to version `n + 1`.
+const MIGRATIONS: &[fn(&mut toml::Table)] = &[migrate_v0_to_v1];
+
+const PLACEHOLDER_API_KEY: &str = "your-openai-api-key-here";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub enum LlmBackend {
     OpenAI,
+    #[default]
     Ollama,
+    /// Answers from a fixtures file; see `MockConfig`.
+    Mock,
+}
+
+/// Either a single backend, `backend = "Ollama"`, or an ordered fallback
+/// chain, `backend = ["Ollama", "OpenAI"]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum BackendSelection {
+    Single(LlmBackend),
+    Chain(Vec<LlmBackend>),
+}
+
+impl Default for BackendSelection {
+    fn default() -> Self {
+        BackendSelection::Single(LlmBackend::default())
+    }
+}
+
+impl From<LlmBackend> for BackendSelection {
+    fn from(backend: LlmBackend) -> Self {
+        BackendSelection::Single(backend)
+    }
+}
+
+impl<'de> Deserialize<'de> for BackendSelection {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::{Error, IntoDeserializer};
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Single(String),
+            Chain(Vec<String>),
+        }
+        // Parse names one by one so a typo reports the valid backends.
+        let parse = |name: String| LlmBackend::deserialize(name.into_deserializer()).map_err(|e: serde::de::value::Error| D::Error::custom(e));
+        match Raw::deserialize(deserializer)? {
+            Raw::Single(name) => Ok(BackendSelection::Single(parse(name)?)),
+            Raw::Chain(names) if names.is_empty() => Err(D::Error::custom("backend list must not be empty")),
+            Raw::Chain(names) => Ok(BackendSelection::Chain(names.into_iter().map(parse).collect::<std::result::Result<_, _>>()?)),
+        }
+    }
+}
+
+impl BackendSelection {
+    /// The backends in the order they are tried.
+    pub fn chain(&self) -> &[LlmBackend] {
+        match self {
+            BackendSelection::Single(backend) => std::slice::from_ref(backend),
+            BackendSelection::Chain(backends) => backends,
+        }
+    }
+
+    /// The backend tried first.
+    pub fn primary(&self) -> LlmBackend {
+        self.chain()[0]
+    }
+
+    pub fn contains(&self, backend: LlmBackend) -> bool {
+        self.chain().contains(&backend)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The API key can be given inline, or obtained from a command, an environment
+/// variable or a file so it does not have to sit in the config in plaintext.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct OpenAIConfig {
-    pub api_key: String,
+    #[serde(skip_serializing_if = "Secret::is_empty")]
+    pub api_key: Secret,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_cmd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<PathBuf>,
     pub model: String,
+    pub endpoint: String,
+}
+
+impl Default for OpenAIConfig {
+    fn default() -> Self {
+        Self {
+            api_key: Secret::default(),
+            api_key_cmd: None,
+            api_key_env: None,
+            api_key_file: None,
+            model: String::from("gpt-3.5-turbo"),
+            endpoint: String::from("https://api.openai.com/v1"),
+        }
+    }
+}
+
+impl OpenAIConfig {
+    /// Resolves the API key from, in order: `api_key_cmd`, `api_key_env`,
+    /// `api_key_file`, `api_key`, and finally `$OPENAI_API_KEY`.
+    pub fn resolve_api_key(&self) -> Result<Secret> {
+        if let Some(command) = &self.api_key_cmd {
+            return secret::from_command(command);
+        }
+        if let Some(name) = &self.api_key_env {
+            return secret::from_env(name);
+        }
+        if let Some(path) = &self.api_key_file {
+            return secret::from_file(&expand_home(path));
+        }
+        if !self.api_key.is_empty() {
+            return Ok(self.api_key.clone());
+        }
+        secret::from_env("OPENAI_API_KEY").map_err(|_| anyhow!(
+            "No OpenAI API key configured. Set one of openai.api_key_cmd, openai.api_key_env, \
+             openai.api_key_file or openai.api_key, or export $OPENAI_API_KEY"
+        ))
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct OllamaConfig {
     pub endpoint: String,
     pub model: String,
+    /// `"generate"` for servers without `/api/chat`.
+    #[serde(skip_serializing_if = "OllamaApi::is_chat")]
+    pub api: OllamaApi,
+    /// How long the model stays loaded between invocations, e.g. `"30m"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(skip_serializing_if = "OllamaOptions::is_empty")]
+    pub options: OllamaOptions,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::from("http://localhost:11434"),
+            model: String::from("llama3.2"),
+            api: OllamaApi::default(),
+            keep_alive: None,
+            options: OllamaOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MockConfig {
+    /// JSON file with canned responses, see `mock::Fixtures`.
+    pub fixtures: PathBuf,
+}
+
+/// A named set of overrides selected with `--profile <name>` or `default_profile`.
+/// Any field left out falls back to the top-level configuration.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Profile {
+    pub backend: Option<BackendSelection>,
+    pub model: Option<String>,
+    pub endpoint: Option<String>,
+    pub additional_context: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
-    pub backend: LlmBackend,
+    pub config_version: u32,
+    pub backend: BackendSelection,
+    /// Seconds to wait for each backend in a fallback chain before trying the next.
+    pub backend_timeout: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    pub additional_context: String,
     pub openai: Option<OpenAIConfig>,
     pub ollama: Option<OllamaConfig>,
-    pub additional_context: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mock: Option<MockConfig>,
+    pub thresholds: Thresholds,
+    #[serde(skip_serializing_if = "UsageConfig::is_empty")]
+    pub usage: UsageConfig,
+    #[serde(skip_serializing_if = "AuditConfig::is_empty")]
+    pub audit: AuditConfig,
+    #[serde(skip_serializing_if = "RedactConfig::is_empty")]
+    pub redact: RedactConfig,
+    /// Profiles queried together by `--ensemble`; when empty, each backend in
+    /// `backend` is queried instead.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ensemble: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+    /// Directory the file was loaded from; prompt overrides live in `prompts/` below it.
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
+    /// The profile merged in by `apply_profile`, if any.
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            backend: BackendSelection::default(),
+            backend_timeout: 60,
+            default_profile: None,
+            additional_context: String::new(),
+            openai: None,
+            ollama: None,
+            mock: None,
+            thresholds: Thresholds::default(),
+            usage: UsageConfig::default(),
+            audit: AuditConfig::default(),
+            redact: RedactConfig::default(),
+            ensemble: Vec::new(),
+            profiles: HashMap::new(),
+            config_dir: None,
+            active_profile: None,
+        }
+    }
 }
 
 impl Config {
-    pub fn load() -> Result<Self> {
-        let config_path = get_config_file_path();
+    /// Loads the configuration, upgrading older files in place (after writing a
+    /// backup next to them) and creating a default file when none exists.
+    pub fn load(config_path: &Path) -> Result<Self> {
         if !config_path.exists() {
-            return Self::create_default_config(&config_path);
-        }
-        let config_str = std::fs::read_to_string(&config_path)?;
-        Ok(toml::from_str(&config_str)?)
-    }
-
-    fn create_default_config(config_path: &PathBuf) -> Result<Self> {
-        let default_config = Config {
-            backend: LlmBackend::Ollama,
-            openai: Some(OpenAIConfig {
-                api_key: String::from("your-openai-api-key-here"),
-                model: String::from("gpt-3.5-turbo"),
-            }),
-            ollama: Some(OllamaConfig {
-                endpoint: String::from("http://localhost:11434"),
-                model: String::from("llama3.2"),
-            }),
-            additional_context: String::from("running macos and generally zsh, is a developer, and uses brew"),
+            return Self::create_default_config(config_path);
+        }
+        let config_str = std::fs::read_to_string(config_path)?;
+
+        let mut table: toml::Table = toml::from_str(&config_str)
+            .map_err(|e| config_error(config_path, e))?;
+        let version = table
+            .get("config_version")
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0) as u32;
+        if version < CONFIG_VERSION {
+            for migration in &MIGRATIONS[version as usize..] {
+                migration(&mut table);
+            }
+            table.insert(String::from("config_version"), toml::Value::Integer(CONFIG_VERSION as i64));
+
+            // Only rewrite the file when the upgraded version actually loads.
+            let upgraded_str = toml::to_string_pretty(&table)?;
+            let config = Self::parse(&upgraded_str, config_path)?;
+
+            let backup_path = config_path.with_extension(format!("toml.v{}.bak", version));
+            std::fs::copy(config_path, &backup_path)?;
+            secret::write_private(config_path, &upgraded_str)?;
+            eprintln!(
+                "Upgraded {} to config version {} (backup saved to {})",
+                config_path.display(), CONFIG_VERSION, backup_path.display()
+            );
+            return Ok(config);
+        } else if version > CONFIG_VERSION {
+            eprintln!(
+                "Warning: {} was written by a newer version of toli (config version {}); unknown settings are ignored",
+                config_path.display(), version
+            );
+        }
+
+        let config = Self::parse(&config_str, config_path)?;
+        if config.openai.as_ref().is_some_and(|o| !o.api_key.is_empty()) {
+            secret::warn_if_accessible_by_others(config_path, "Configuration file");
+        }
+        Ok(config)
+    }
+
+    /// Parses a configuration file's contents, reporting errors with the file
+    /// name and the offending line.
+    pub fn parse(config_str: &str, config_path: &Path) -> Result<Self> {
+        let mut config: Self = toml::from_str(config_str).map_err(|e| config_error(config_path, e))?;
+        config.validate().map_err(|e| config_error(config_path, format!("{}\n", e)))?;
+        config.config_dir = config_path.parent().map(Path::to_path_buf);
+        Ok(config)
+    }
+
+    /// Checks what the file's types alone don't, such as thresholds between 0
+    /// and 1 and redaction patterns that compile.
+    pub fn validate(&self) -> Result<()> {
+        self.thresholds.validate()?;
+        self.usage.validate().map_err(|e| anyhow!(e))?;
+        self.redact.validate().map_err(|e| anyhow!(e))?;
+        Ok(())
+    }
+
+    /// The prompt templates for this configuration, including any overrides in
+    /// `<config dir>/prompts/` and `<config dir>/prompts/<profile>/`.
+    pub fn prompts(&self) -> Result<Prompts> {
+        match &self.config_dir {
+            Some(dir) => Prompts::load(&dir.join("prompts"), self.active_profile.as_deref()),
+            None => Ok(Prompts::builtin()),
+        }
+    }
+
+    /// A short description of the selected backends and models, e.g.
+    /// `Ollama/llama3.2` or `Ollama/llama3.2 -> OpenAI/gpt-4` for a fallback chain.
+    pub fn model_label(&self) -> String {
+        self.backend
+            .chain()
+            .iter()
+            .map(|backend| self.backend_label(*backend))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// A short description of `backend` and its configured model, e.g. `Ollama/llama3.2`.
+    pub fn backend_label(&self, backend: LlmBackend) -> String {
+        match backend {
+            LlmBackend::OpenAI => format!("OpenAI/{}", self.openai.clone().unwrap_or_default().model),
+            LlmBackend::Ollama => format!("Ollama/{}", self.ollama.clone().unwrap_or_default().model),
+            LlmBackend::Mock => String::from("Mock"),
+        }
+    }
+
+    /// Merges the named profile (or `default_profile` when `name` is `None`) into
+    /// the top-level settings. Model and endpoint apply to the profile's backend,
+    /// or the first one of a fallback chain.
+    pub fn apply_profile(&mut self, name: Option<&str>) -> Result<()> {
+        let name = match name.map(String::from).or_else(|| self.default_profile.clone()) {
+            Some(name) => name,
+            None => return Ok(()),
         };
+        let profile = self.profiles.remove(&name).ok_or_else(|| {
+            let mut available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            available.sort();
+            anyhow!("Profile '{}' not found in config (available: {})", name,
+                if available.is_empty() { String::from("none") } else { available.join(", ") })
+        })?;
+
+        if let Some(backend) = profile.backend {
+            self.backend = backend;
+        }
+        if let Some(context) = profile.additional_context {
+            self.additional_context = context;
+        }
+        match self.backend.primary() {
+            LlmBackend::OpenAI => {
+                let openai = self.openai.get_or_insert_with(OpenAIConfig::default);
+                if let Some(model) = profile.model {
+                    openai.model = model;
+                }
+                if let Some(endpoint) = profile.endpoint {
+                    openai.endpoint = endpoint;
+                }
+            }
+            LlmBackend::Ollama => {
+                let ollama = self.ollama.get_or_insert_with(OllamaConfig::default);
+                if let Some(model) = profile.model {
+                    ollama.model = model;
+                }
+                if let Some(endpoint) = profile.endpoint {
+                    ollama.endpoint = endpoint;
+                }
+            }
+            LlmBackend::Mock => {}
+        }
+        self.active_profile = Some(name);
+        Ok(())
+    }
 
-        let config_str = toml::to_string_pretty(&default_config)?;
+    /// Writes the configuration to `config_path`, creating parent directories.
+    /// The file is only readable by its owner since it may contain an API key.
+    pub fn save(&self, config_path: &Path) -> Result<()> {
+        let config_str = toml::to_string_pretty(self)?;
         if let Some(parent) = config_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        std::fs::write(config_path, config_str)?;
+        secret::write_private(config_path, &config_str)
+    }
+
+    /// A starting configuration that uses the local Ollama server and describes
+    /// the current environment, without any placeholder credentials.
+    pub fn default_for_environment() -> Self {
+        Config {
+            additional_context: detect_environment_context(),
+            ollama: Some(OllamaConfig::default()),
+            ..Config::default()
+        }
+    }
+
+    fn create_default_config(config_path: &Path) -> Result<Self> {
+        let mut default_config = Self::default_for_environment();
+        default_config.save(config_path)?;
+        default_config.config_dir = config_path.parent().map(Path::to_path_buf);
+        eprintln!(
+            "Created a default configuration at {} (Ollama, model llama3.2). Run 'toli config init' to customise it.",
+            config_path.display()
+        );
 
         Ok(default_config)
     }
 }
 
-fn get_config_file_path() -> PathBuf {
-    let home = dirs::home_dir().expect("Could not find home directory");
-    home.join(".config").join("howto").join("config.toml")
-}
\ No newline at end of file
+/// Builds a short description of the user's environment, e.g. "running linux and generally bash".
+pub fn detect_environment_context() -> String {
+    format!("running {} and generally {}", std::env::consts::OS, detect_shell())
+}
+
+/// The name of the user's login shell from `$SHELL`, e.g. "zsh".
+pub fn detect_shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .and_then(|s| PathBuf::from(s).file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| String::from("sh"))
+}
+
+fn config_error(config_path: &Path, error: impl std::fmt::Display) -> anyhow::Error {
+    anyhow!(
+        "Invalid configuration in {}\n{}Run 'toli config edit' to fix it, or 'toli config init' to start over.",
+        config_path.display(), error
+    )
+}
+
+/// Version 0 is the original `howto` layout, which was written with a
+/// placeholder OpenAI key that would otherwise be sent to the API.
+fn migrate_v0_to_v1(table: &mut toml::Table) {
+    if let Some(openai) = table.get_mut("openai").and_then(|v| v.as_table_mut()) {
+        if openai.get("api_key").and_then(|v| v.as_str()) == Some(PLACEHOLDER_API_KEY) {
+            openai.remove("api_key");
+        }
+    }
+}
+
+/// Resolves the configuration file location, in order of precedence:
+/// the `--config` flag, `$TOLI_CONFIG`, `$XDG_CONFIG_HOME/toli/config.toml`
+/// and finally `~/.config/toli/config.toml`.
+///
+/// For the default locations, a config left behind by the old `howto` name is
+/// moved into place the first time it is looked up.
+pub fn get_config_file_path(cli_override: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = cli_override {
+        return Ok(path.to_path_buf());
+    }
+    if let Some(path) = non_empty_env("TOLI_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+
+    let config_path = config_home()?.join("toli").join("config.toml");
+    // `howto` always used ~/.config, whatever $XDG_CONFIG_HOME says.
+    if let (false, Some(home)) = (config_path.exists(), dirs::home_dir()) {
+        migrate_legacy_config(&home.join(".config").join("howto").join("config.toml"), &config_path)?;
+    }
+    Ok(config_path)
+}
+
+fn config_home() -> Result<PathBuf> {
+    // The XDG spec says relative paths must be ignored.
+    if let Some(dir) = non_empty_env("XDG_CONFIG_HOME").map(PathBuf::from).filter(|p| p.is_absolute()) {
+        return Ok(dir);
+    }
+    let home = dirs::home_dir().ok_or_else(|| {
+        anyhow!("Could not determine your home directory; set $XDG_CONFIG_HOME or $TOLI_CONFIG, or pass --config")
+    })?;
+    Ok(home.join(".config"))
+}
+
+fn migrate_legacy_config(legacy_path: &Path, config_path: &Path) -> Result<()> {
+    if !legacy_path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Fall back to copying when the two directories are on different filesystems.
+    if std::fs::rename(legacy_path, config_path).is_err() {
+        std::fs::copy(legacy_path, config_path).map_err(|e| {
+            anyhow!("Failed to migrate {} to {}: {}", legacy_path.display(), config_path.display(), e)
+        })?;
+        std::fs::remove_file(legacy_path).ok();
+    }
+    if let Some(legacy_dir) = legacy_path.parent() {
+        // Only succeeds when nothing else was stored next to the old config.
+        std::fs::remove_dir(legacy_dir).ok();
+    }
+    eprintln!("Moved configuration from {} to {}", legacy_path.display(), config_path.display());
+    Ok(())
+}
+
+/// Expands a leading `~/` so key files can be written portably in the config.
+pub(crate) fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
@@ -0,0 +1,336 @@
+use async_trait::async_trait;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::breakdown::{self, Breakdown};
+use crate::classify::Thresholds;
+use crate::llm::{LLMBackend, CommandOption, ParseInfo, RawReply, ResponseType, Suggestion};
+use crate::prompts::{Prompt, Prompts, Task};
+use crate::redact::{Redactions, Redactor};
+use crate::usage::{self, Usage, UsageRecord};
+
+/// How many times the model is asked again after a reply that isn't valid JSON.
+const MAX_ATTEMPTS: usize = 5;
+
+/// Added to the system prompt of redacted requests.
+const REDACTION_NOTE: &str = "Some values were replaced with placeholders such as REDACTED_EMAIL_1. \
+Use the placeholders unchanged wherever the values belong.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// One turn of a chat conversation.
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self { role, content: content.into() }
+    }
+}
+
+/// A model's answer, with the token usage the provider reported for it.
+#[derive(Debug, Clone, Default)]
+pub struct Reply {
+    pub content: String,
+    pub usage: Option<Usage>,
+}
+
+impl From<String> for Reply {
+    fn from(content: String) -> Self {
+        Self { content, usage: None }
+    }
+}
+
+/// A provider could not be reached or did not answer in time, as opposed to
+/// answering with an error. A fallback chain moves on to the next backend.
+#[derive(Debug)]
+pub struct ConnectionError(pub String);
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// The part of a model provider that differs between providers: sending a
+/// conversation and getting the reply back as text. Everything else (prompts,
+/// retries, parsing) is done by [`Engine`].
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends `messages` and returns the model's reply. Failures to reach the
+    /// provider are reported as [`ConnectionError`].
+    async fn chat(&self, messages: &[Message]) -> Result<Reply>;
+
+    /// Like `chat`, but hands the reply to `on_chunk` as it arrives. Providers
+    /// without streaming deliver the whole reply as a single chunk.
+    async fn chat_stream(&self, messages: &[Message], on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send)) -> Result<Reply> {
+        let reply = self.chat(messages).await?;
+        on_chunk(&reply.content);
+        Ok(reply)
+    }
+
+    /// Like `chat`, for a request made on behalf of `task`. Providers with
+    /// per-task settings override this; by default the task is ignored.
+    async fn chat_for(&self, task: Task, messages: &[Message]) -> Result<Reply> {
+        let _ = task;
+        self.chat(messages).await
+    }
+
+    /// Provider and model name, recorded with usage.
+    fn describe(&self) -> (&str, &str) {
+        ("custom", "unknown")
+    }
+}
+
+/// Implements toli's tasks on top of any [`Transport`], so every provider gets
+/// the same prompts, retries and reply parsing.
+pub struct Engine<T> {
+    transport: T,
+    prompts: Prompts,
+    thresholds: Thresholds,
+    usage: Mutex<Vec<UsageRecord>>,
+    replies: Mutex<Vec<RawReply>>,
+    redactor: Option<Redactor>,
+}
+
+impl<T: Transport> Engine<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            prompts: Prompts::builtin(),
+            thresholds: Thresholds::default(),
+            usage: Mutex::default(),
+            replies: Mutex::default(),
+            redactor: None,
+        }
+    }
+
+    /// Uses `prompts` instead of the built-in templates.
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    /// Classifies suggestions with `thresholds` instead of the defaults.
+    pub fn with_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Redacts requests with `redactor` and restores the placeholders in replies.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Sends `messages` for `task`, recording the reply and the usage the
+    /// provider reports. With a redactor, the model only sees placeholders.
+    async fn send(&self, task: Task, messages: &[Message]) -> Result<String> {
+        let started = Instant::now();
+        let mut redactions = Redactions::default();
+        let redacted = self.redactor.as_ref().map(|redactor| redact(redactor, messages, &mut redactions));
+        let reply = self.transport.chat_for(task, redacted.as_deref().unwrap_or(messages)).await?;
+        let (backend, model) = self.transport.describe();
+        self.replies.lock().unwrap().push(RawReply {
+            backend: backend.to_string(),
+            model: model.to_string(),
+            task: task.name().to_string(),
+            prompt_version: self.prompts.version().to_string(),
+            content: reply.content.clone(),
+        });
+        if let Some(usage) = reply.usage {
+            self.usage.lock().unwrap().push(UsageRecord {
+                timestamp: usage::now(),
+                backend: backend.to_string(),
+                model: model.to_string(),
+                task: task.name().to_string(),
+                prompt_version: self.prompts.version().to_string(),
+                usage,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+        Ok(redactions.restore(&reply.content))
+    }
+
+    /// Asks for a JSON array of options, asking again with the failed replies
+    /// when the answer can't be parsed. The options are `None` when every attempt failed.
+    async fn request_options(&self, task: Task, prompt: Prompt) -> Result<(Option<Vec<CommandOption>>, ParseInfo)> {
+        self.request_parsed(task, prompt, "a valid JSON array", parse_options).await
+    }
+
+    /// Sends `prompt` until `parse` accepts the reply, feeding the failed replies
+    /// back to the model. `expected` describes the format in the retry prompt.
+    async fn request_parsed<R>(
+        &self,
+        task: Task,
+        prompt: Prompt,
+        expected: &str,
+        parse: impl Fn(&str) -> Option<(R, bool)> + Send + Sync,
+    ) -> Result<(Option<R>, ParseInfo)> {
+        let feedback = [".   ", "..  ", "... ", "...."];
+        let mut messages = vec![Message::new(Role::System, prompt.system), Message::new(Role::User, prompt.user)];
+        let mut failed_responses = Vec::new();
+
+        for attempt in 0..MAX_ATTEMPTS {
+            if attempt > 0 {
+                eprint!("\rThinking{}", feedback[attempt % feedback.len()]);
+                messages.truncate(2);
+                messages.push(Message::new(Role::User, retry_prompt(&failed_responses, expected)));
+            }
+
+            let reply = self.send(task, &messages).await?;
+            if let Some((parsed, repaired)) = parse(&reply) {
+                if attempt > 0 {
+                    eprintln!();
+                }
+                return Ok((Some(parsed), ParseInfo { attempts: attempt + 1, repaired, failed: false }));
+            }
+            failed_responses.push(reply);
+        }
+
+        eprintln!();
+        Ok((None, ParseInfo { attempts: MAX_ATTEMPTS, repaired: false, failed: true }))
+    }
+}
+
+#[async_trait]
+impl<T: Transport> LLMBackend for Engine<T> {
+    async fn translate_to_command(&self, query: &str, additional_context: &str) -> Result<Vec<ResponseType>> {
+        let prompt = self.prompts.render(Task::Translate, query, additional_context)?;
+        Ok(match self.request_options(Task::Translate, prompt).await? {
+            (Some(options), parse) => options
+                .into_iter()
+                .map(|option| self.thresholds.classify(Suggestion { parse: parse.clone(), ..Suggestion::new(option) }))
+                .collect(),
+            (None, parse) => vec![ResponseType::parse_failure(parse.attempts)],
+        })
+    }
+
+    async fn explain_command(&self, command: &str, additional_context: &str) -> Result<ResponseType> {
+        let prompt = self.prompts.render(Task::Explain, command, additional_context)?;
+        let messages = [Message::new(Role::System, prompt.system), Message::new(Role::User, prompt.user)];
+        let explanation = self.send(Task::Explain, &messages).await?;
+
+        Ok(ResponseType::Command(Suggestion::new(CommandOption {
+            command: command.to_string(),
+            explanation: explanation.trim().to_string(),
+            confidence: 1.0
+        })))
+    }
+
+    async fn suggest_aliases(&self, command: &str, additional_context: &str) -> Result<Vec<CommandOption>> {
+        let prompt = self.prompts.render(Task::Aliases, command, additional_context)?;
+        Ok(self.request_options(Task::Aliases, prompt).await?.0.unwrap_or_default())
+    }
+
+    async fn explain_breakdown(&self, command: &str, additional_context: &str) -> Result<Breakdown> {
+        let segments = breakdown::segments(command)
+            .ok_or_else(|| anyhow!("Could not split '{}' into words; check its quoting", command))?;
+        let numbered = breakdown::numbered(&segments);
+        let prompt = self.prompts.render_with(Task::Breakdown, command, additional_context, &[("segments", &numbered)])?;
+        let parse = |reply: &str| breakdown::parse_reply(command, &segments, reply).map(|b| (b, false));
+        match self.request_parsed(Task::Breakdown, prompt, "a valid JSON object", parse).await? {
+            (Some(breakdown), _) => Ok(breakdown),
+            (None, parse) => Err(anyhow!("Failed to get a structured explanation after {} attempts", parse.attempts)),
+        }
+    }
+
+    async fn summarize_script(&self, sections: &str, additional_context: &str) -> Result<String> {
+        let prompt = self.prompts.render(Task::Summary, sections, additional_context)?;
+        let messages = [Message::new(Role::System, prompt.system), Message::new(Role::User, prompt.user)];
+        Ok(self.send(Task::Summary, &messages).await?.trim().to_string())
+    }
+
+    fn usage(&self) -> Vec<UsageRecord> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    fn replies(&self) -> Vec<RawReply> {
+        self.replies.lock().unwrap().clone()
+    }
+}
+
+/// `messages` with sensitive values replaced, noting the placeholders in the
+/// system prompt when there are any.
+fn redact(redactor: &Redactor, messages: &[Message], redactions: &mut Redactions) -> Vec<Message> {
+    let mut redacted: Vec<Message> = messages
+        .iter()
+        .map(|message| Message::new(message.role, redactor.redact(&message.content, redactions)))
+        .collect();
+    if !redactions.is_empty() {
+        if let Some(system) = redacted.iter_mut().find(|message| message.role == Role::System) {
+            system.content = format!("{}\n\n{}", system.content.trim_end(), REDACTION_NOTE);
+        }
+    }
+    redacted
+}
+
+fn retry_prompt(failed_responses: &[String], expected: &str) -> String {
+    let mut prompt = String::from("Previous attempts failed to generate valid JSON. Here are the failed responses:\n");
+    for (i, response) in failed_responses.iter().enumerate() {
+        prompt.push_str(&format!("\nAttempt {}: {}\n", i + 1, response));
+    }
+    prompt.push_str(&format!("\nPlease ensure your response is {}.", expected));
+    prompt
+}
+
+/// Parses a non-empty JSON array of options from a model reply, tolerating
+/// surrounding prose or code fences and raw newlines inside strings. Also
+/// returns whether the reply needed any of that leniency.
+pub fn parse_options(reply: &str) -> Option<(Vec<CommandOption>, bool)> {
+    let reply = reply.trim();
+    let array = match (reply.find('['), reply.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => return None,
+    };
+
+    [array.to_string(), escape_raw_control_chars(array)]
+        .iter()
+        .enumerate()
+        .find_map(|(i, candidate)| {
+            let options = serde_json::from_str::<Vec<CommandOption>>(candidate).ok()?;
+            Some((options, i > 0 || array.len() != reply.len()))
+        })
+        .filter(|(options, _)| !options.is_empty())
+}
+
+/// Escapes newlines and tabs that appear unescaped inside JSON strings, a
+/// common mistake when models return multi-line commands.
+pub(crate) fn escape_raw_control_chars(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut backslash = false;
+    for c in json.chars() {
+        match c {
+            '\n' if in_string => escaped.push_str("\\n"),
+            '\r' if in_string => escaped.push_str("\\r"),
+            '\t' if in_string => escaped.push_str("\\t"),
+            _ => {
+                if in_string && c == '"' && !backslash {
+                    in_string = false;
+                } else if !in_string && c == '"' {
+                    in_string = true;
+                }
+                escaped.push(c);
+            }
+        }
+        backslash = c == '\\' && !backslash;
+    }
+    escaped
+}
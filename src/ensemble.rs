@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use anyhow::{Result, anyhow};
+use std::cmp::Reverse;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use crate::breakdown::Breakdown;
+use crate::config::Config;
+use crate::llm::{self, LLMBackend, CommandOption, RawReply, ResponseType};
+use crate::normalize;
+use crate::usage::UsageRecord;
+
+/// One of the models queried by an [`Ensemble`].
+pub struct Member {
+    pub label: String,
+    pub backend: Arc<dyn LLMBackend>,
+    /// Replaces the caller's environment description, e.g. a profile's own.
+    pub context: Option<String>,
+}
+
+/// Asks several models at once and merges their suggestions: commands that
+/// are the same after normalization are combined, and the result is ranked by
+/// how many models agree and then by confidence. Each suggestion lists the
+/// models that made it in `sources`.
+///
+/// Explanations, breakdowns and aliases come from the first member.
+pub struct Ensemble {
+    members: Vec<Member>,
+}
+
+impl Ensemble {
+    pub fn new(members: Vec<Member>) -> Self {
+        Self { members }
+    }
+
+    /// Members are the profiles named in `ensemble`, each applied to `config`,
+    /// or else every backend listed in `backend`.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let members = if config.ensemble.is_empty() {
+            config.backend
+                .chain()
+                .iter()
+                .map(|backend| Ok(Member {
+                    label: config.backend_label(*backend),
+                    backend: Arc::from(llm::backend_from_config(config, *backend)?),
+                    context: None,
+                }))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            config.ensemble
+                .iter()
+                .map(|name| {
+                    let mut profile_config = config.clone();
+                    profile_config.apply_profile(Some(name))?;
+                    Ok(Member {
+                        label: format!("{} ({})", profile_config.model_label(), name),
+                        backend: Arc::from(llm::from_config(&profile_config)?),
+                        context: Some(profile_config.additional_context),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        if members.len() < 2 {
+            return Err(anyhow!(
+                "Ensemble mode needs at least two models: list several backends in 'backend' or name profiles in 'ensemble'"
+            ));
+        }
+        Ok(Self::new(members))
+    }
+
+    /// Number of models queried.
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+}
+
+#[async_trait]
+impl LLMBackend for Ensemble {
+    async fn translate_to_command(&self, query: &str, additional_context: &str) -> Result<Vec<ResponseType>> {
+        let mut tasks = JoinSet::new();
+        for (i, member) in self.members.iter().enumerate() {
+            let backend = member.backend.clone();
+            let query = query.to_string();
+            let context = member.context.clone().unwrap_or_else(|| additional_context.to_string());
+            tasks.spawn(async move { (i, backend.translate_to_command(&query, &context).await) });
+        }
+
+        let mut answers: Vec<Vec<ResponseType>> = vec![Vec::new(); self.members.len()];
+        let mut first_error = None;
+        while let Some(joined) = tasks.join_next().await {
+            let (i, result) = joined?;
+            match result {
+                Ok(options) => answers[i] = options,
+                Err(e) => {
+                    eprintln!("{} failed: {}", self.members[i].label, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        let merged = merge(&self.members, &answers);
+        if !merged.is_empty() {
+            return Ok(merged);
+        }
+        match first_error {
+            Some(e) if answers.iter().all(Vec::is_empty) => Err(e),
+            // Nothing but unparseable replies; pass one of them on.
+            _ => Ok(answers.into_iter().flatten().take(1).collect()),
+        }
+    }
+
+    async fn explain_command(&self, command: &str, additional_context: &str) -> Result<ResponseType> {
+        self.members[0].backend.explain_command(command, additional_context).await
+    }
+
+    async fn explain_breakdown(&self, command: &str, additional_context: &str) -> Result<Breakdown> {
+        self.members[0].backend.explain_breakdown(command, additional_context).await
+    }
+
+    async fn suggest_aliases(&self, command: &str, additional_context: &str) -> Result<Vec<CommandOption>> {
+        self.members[0].backend.suggest_aliases(command, additional_context).await
+    }
+
+    async fn summarize_script(&self, sections: &str, additional_context: &str) -> Result<String> {
+        self.members[0].backend.summarize_script(sections, additional_context).await
+    }
+
+    fn usage(&self) -> Vec<UsageRecord> {
+        self.members.iter().flat_map(|member| member.backend.usage()).collect()
+    }
+
+    fn replies(&self) -> Vec<RawReply> {
+        self.members.iter().flat_map(|member| member.backend.replies()).collect()
+    }
+}
+
+/// Groups equivalent suggestions, keeping the most confident of each group.
+fn merge(members: &[Member], answers: &[Vec<ResponseType>]) -> Vec<ResponseType> {
+    // (normalized command, best suggestion, indexes of the members that made it)
+    let mut groups: Vec<(String, ResponseType, Vec<usize>)> = Vec::new();
+    for (member, options) in answers.iter().enumerate() {
+        for option in options.iter().filter(|o| !o.is_parse_failure()) {
+            let command = &option.option().command;
+            let key = normalize::canonical(command).unwrap_or_else(|| command.trim().to_string());
+            match groups.iter_mut().find(|(existing, _, _)| *existing == key) {
+                Some((_, best, sources)) => {
+                    if option.option().confidence > best.option().confidence {
+                        *best = option.clone();
+                    }
+                    if !sources.contains(&member) {
+                        sources.push(member);
+                    }
+                }
+                None => groups.push((key, option.clone(), vec![member])),
+            }
+        }
+    }
+
+    // Stable, so ties keep the order in which the suggestions were first seen.
+    groups.sort_by(|(_, a, a_sources), (_, b, b_sources)| {
+        Reverse(a_sources.len())
+            .cmp(&Reverse(b_sources.len()))
+            .then(b.option().confidence.total_cmp(&a.option().confidence))
+    });
+    groups
+        .into_iter()
+        .map(|(_, mut best, sources)| {
+            best.suggestion_mut().sources = sources.into_iter().map(|i| members[i].label.clone()).collect();
+            best
+        })
+        .collect()
+}
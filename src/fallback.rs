@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::future::Future;
+use std::time::Duration;
+use crate::breakdown::Breakdown;
+use crate::engine::ConnectionError;
+use crate::llm::{LLMBackend, CommandOption, RawReply, ResponseType};
+use crate::usage::UsageRecord;
+
+/// Tries backends in order, moving on to the next when one can't be reached,
+/// doesn't answer within the timeout or only returns unparseable replies.
+/// Suggestions are labelled with the backend that answered.
+pub struct Fallback {
+    backends: Vec<(String, Box<dyn LLMBackend>)>,
+    timeout: Duration,
+}
+
+impl Fallback {
+    /// `backends` are tried in order; each is identified by its label in messages.
+    pub fn new(backends: Vec<(String, Box<dyn LLMBackend>)>, timeout: Duration) -> Self {
+        Self { backends, timeout }
+    }
+
+    /// Runs `task` against each backend until one answers. `unusable` marks
+    /// answers that should also be retried on the next backend. Returns the
+    /// answer and the label of the backend that gave it.
+    async fn first_answer<'a, T, F, Fut>(&'a self, task: F, unusable: impl Fn(&T) -> bool) -> Result<(T, &'a str)>
+    where
+        F: Fn(&'a dyn LLMBackend) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        for (i, (label, backend)) in self.backends.iter().enumerate() {
+            let result = match tokio::time::timeout(self.timeout, task(backend.as_ref())).await {
+                Ok(result) => result,
+                Err(_) => Err(ConnectionError(format!("No answer within {}s", self.timeout.as_secs())).into()),
+            };
+            let reason = match &result {
+                Err(e) if e.is::<ConnectionError>() => e.to_string(),
+                Ok(answer) if unusable(answer) => String::from("no usable reply"),
+                _ => {
+                    if i > 0 {
+                        eprintln!("Answered by {}", label);
+                    }
+                    return result.map(|answer| (answer, label.as_str()));
+                }
+            };
+            match self.backends.get(i + 1) {
+                Some((next, _)) => eprintln!("{} failed ({}); trying {}", label, reason, next),
+                None => return result.map(|answer| (answer, label.as_str())),
+            }
+        }
+        unreachable!("a fallback chain has at least one backend")
+    }
+}
+
+#[async_trait]
+impl LLMBackend for Fallback {
+    async fn translate_to_command(&self, query: &str, additional_context: &str) -> Result<Vec<ResponseType>> {
+        let (mut options, source) = self
+            .first_answer(
+                |backend| backend.translate_to_command(query, additional_context),
+                |options: &Vec<ResponseType>| options.iter().all(ResponseType::is_parse_failure),
+            )
+            .await?;
+        for option in &mut options {
+            option.suggestion_mut().sources = vec![source.to_string()];
+        }
+        Ok(options)
+    }
+
+    async fn explain_command(&self, command: &str, additional_context: &str) -> Result<ResponseType> {
+        let (mut explanation, source) = self
+            .first_answer(|backend| backend.explain_command(command, additional_context), |_| false)
+            .await?;
+        explanation.suggestion_mut().sources = vec![source.to_string()];
+        Ok(explanation)
+    }
+
+    async fn explain_breakdown(&self, command: &str, additional_context: &str) -> Result<Breakdown> {
+        let (breakdown, _) = self
+            .first_answer(|backend| backend.explain_breakdown(command, additional_context), |_| false)
+            .await?;
+        Ok(breakdown)
+    }
+
+    async fn suggest_aliases(&self, command: &str, additional_context: &str) -> Result<Vec<CommandOption>> {
+        let (aliases, _) = self
+            .first_answer(|backend| backend.suggest_aliases(command, additional_context), |_| false)
+            .await?;
+        Ok(aliases)
+    }
+
+    async fn summarize_script(&self, sections: &str, additional_context: &str) -> Result<String> {
+        let (summary, _) = self
+            .first_answer(|backend| backend.summarize_script(sections, additional_context), |_| false)
+            .await?;
+        Ok(summary)
+    }
+
+    fn usage(&self) -> Vec<UsageRecord> {
+        self.backends.iter().flat_map(|(_, backend)| backend.usage()).collect()
+    }
+
+    fn replies(&self) -> Vec<RawReply> {
+        self.backends.iter().flat_map(|(_, backend)| backend.replies()).collect()
+    }
+}
@@ -0,0 +1,51 @@
+//! TOLI (Terminal Intelligence & Learning Operator) translates natural
+//! language into shell commands using a language model.
+//!
+//! The `toli` binary is a thin command-line interface over this library; other
+//! tools can embed the translation engine directly:
+//!
+//! ```no_run
+//! use toli::{Config, ResponseType};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config_path = toli::config::get_config_file_path(None)?;
+//! let config = Config::load(&config_path)?;
+//! let backend = toli::llm::from_config(&config)?;
+//!
+//! for option in backend.translate_to_command("list all pdf files", &config.additional_context).await? {
+//!     if let ResponseType::Command(suggestion) = option {
+//!         println!("{} - {} ({} risk)", suggestion.option.command, suggestion.option.explanation, suggestion.risk);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod audit;
+pub mod breakdown;
+pub mod classify;
+pub mod config;
+pub mod engine;
+pub mod ensemble;
+pub mod fallback;
+pub mod llm;
+pub mod mock;
+pub mod normalize;
+pub mod ollama;
+pub mod openai;
+pub mod prompts;
+pub mod redact;
+pub mod sandbox;
+pub mod script;
+pub mod secret;
+pub mod shell;
+pub mod undo;
+pub mod usage;
+
+pub use config::Config;
+pub use engine::{Engine, Transport};
+pub use ensemble::Ensemble;
+pub use llm::{CommandOption, LLMBackend, RawReply, ResponseType, Suggestion};
+pub use mock::MockBackend;
+pub use ollama::OllamaBackend;
+pub use openai::OpenAIBackend;
@@ -1,14 +1,90 @@
 use async_trait::async_trait;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use crate::breakdown::Breakdown;
+use crate::classify::{self, Category, Risk, Thresholds};
+use crate::config::{Config, LlmBackend};
+use crate::engine::Engine;
+use crate::fallback::Fallback;
+use crate::mock::MockBackend;
+use crate::ollama::OllamaBackend;
+use crate::openai::OpenAIBackend;
+use crate::usage::UsageRecord;
 
+/// Explanation of the option returned when every retry produced an unparseable reply.
+pub const RETRIES_EXHAUSTED: &str = "Failed to generate valid command options after multiple attempts.";
+
+/// A suggestion, classified by how confident toli is that it can be run as is.
+/// Every variant keeps the full suggestion, so explanations and confidence
+/// are available whichever way an option was classified.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ResponseType {
-    Command(CommandOption),
-    ScriptRecommended(String),
-    Uncertain(String)
+    Command(Suggestion),
+    ScriptRecommended(Suggestion),
+    Uncertain(Suggestion),
+}
+
+impl ResponseType {
+    /// The placeholder returned when no reply from the model could be parsed.
+    pub fn parse_failure(attempts: usize) -> Self {
+        let option = CommandOption {
+            command: String::new(),
+            explanation: String::from(RETRIES_EXHAUSTED),
+            confidence: 0.0,
+        };
+        ResponseType::Uncertain(Suggestion {
+            parse: ParseInfo { attempts, repaired: false, failed: true },
+            ..Suggestion::new(option)
+        })
+    }
+
+    pub fn suggestion(&self) -> &Suggestion {
+        match self {
+            ResponseType::Command(s) | ResponseType::ScriptRecommended(s) | ResponseType::Uncertain(s) => s,
+        }
+    }
+
+    pub fn suggestion_mut(&mut self) -> &mut Suggestion {
+        match self {
+            ResponseType::Command(s) | ResponseType::ScriptRecommended(s) | ResponseType::Uncertain(s) => s,
+        }
+    }
+
+    /// The option as the model returned it.
+    pub fn option(&self) -> &CommandOption {
+        &self.suggestion().option
+    }
+
+    /// The command to run, if this option can be executed.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            ResponseType::Command(s) | ResponseType::ScriptRecommended(s) => Some(&s.option.command),
+            ResponseType::Uncertain(_) => None,
+        }
+    }
+
+    /// Whether this is the placeholder a backend returns when it could not
+    /// parse the model's reply at all.
+    pub fn is_parse_failure(&self) -> bool {
+        self.suggestion().parse.failed
+    }
+}
+
+/// Classifies a suggestion with the default thresholds.
+impl From<Suggestion> for ResponseType {
+    fn from(suggestion: Suggestion) -> Self {
+        Thresholds::default().classify(suggestion)
+    }
 }
 
+impl From<CommandOption> for ResponseType {
+    fn from(option: CommandOption) -> Self {
+        Suggestion::new(option).into()
+    }
+}
+
+/// An option as returned by the model.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandOption {
     pub command: String,
@@ -16,17 +92,134 @@ pub struct CommandOption {
     pub confidence: f32,
 }
 
+/// A model's option together with what toli worked out about it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suggestion {
+    #[serde(flatten)]
+    pub option: CommandOption,
+    pub risk: Risk,
+    pub category: Category,
+    pub parse: ParseInfo,
+    /// The backends that suggested this, when several were configured.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+}
+
+impl Suggestion {
+    /// Assesses `option`, assuming it was parsed from the first reply as is.
+    pub fn new(option: CommandOption) -> Self {
+        let (risk, category) = classify::assess(&option.command);
+        Self { option, risk, category, parse: ParseInfo::default(), sources: Vec::new() }
+    }
+}
+
+/// How an option was obtained from the model's reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseInfo {
+    /// Number of replies requested before one could be parsed.
+    pub attempts: usize,
+    /// Whether the options had to be extracted from surrounding text or repaired.
+    pub repaired: bool,
+    /// Set when no reply could be parsed; the option then only carries a message.
+    pub failed: bool,
+}
+
+impl Default for ParseInfo {
+    fn default() -> Self {
+        Self { attempts: 1, repaired: false, failed: false }
+    }
+}
+
+/// A reply exactly as a model sent it, before any parsing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawReply {
+    pub backend: String,
+    pub model: String,
+    /// The task, e.g. `translate` or `explain`.
+    pub task: String,
+    /// The prompt templates used, see [`Prompts::version`](crate::prompts::Prompts::version);
+    /// empty for backends that don't use them.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prompt_version: String,
+    pub content: String,
+}
+
+/// A language model that toli can ask for commands. `additional_context` is a
+/// short description of the user's environment (OS, shell, tools).
 #[async_trait]
-pub trait LLMBackend {
+pub trait LLMBackend: Send + Sync {
+    /// Suggests commands for a natural language query, best first.
     async fn translate_to_command(&self, query: &str, additional_context: &str) -> Result<Vec<ResponseType>>;
+    /// Explains what `command` does; the explanation is returned as a `Command` option.
     async fn explain_command(&self, command: &str, additional_context: &str) -> Result<ResponseType>;
+    /// Suggests shell aliases for working with `command`.
     async fn suggest_aliases(&self, command: &str, additional_context: &str) -> Result<Vec<CommandOption>>;
+    /// Explains `command` word by word. Backends without structured
+    /// explanations return the plain explanation as the summary.
+    async fn explain_breakdown(&self, command: &str, additional_context: &str) -> Result<Breakdown> {
+        let explanation = self.explain_command(command, additional_context).await?;
+        Ok(Breakdown::unexplained(command, explanation.option().explanation.clone()))
+    }
+    /// Summarizes a script from `sections`, the explanations of its parts in
+    /// order. Backends that can't summarize return an empty string.
+    async fn summarize_script(&self, _sections: &str, _additional_context: &str) -> Result<String> {
+        Ok(String::new())
+    }
+    /// Token usage of the requests made so far, oldest first.
+    fn usage(&self) -> Vec<UsageRecord> {
+        Vec::new()
+    }
+    /// The model's replies so far, oldest first.
+    fn replies(&self) -> Vec<RawReply> {
+        Vec::new()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LLMConfig {
-    pub provider: String,
-    pub api_key: Option<String>,
-    pub model: Option<String>,
-    pub endpoint: Option<String>,
-}
\ No newline at end of file
+/// Creates the backend selected in `config`, or a [`Fallback`] over all of
+/// them when a chain is configured.
+pub fn from_config(config: &Config) -> Result<Box<dyn LLMBackend>> {
+    let chain = config.backend.chain();
+    if let [backend] = chain {
+        return backend_from_config(config, *backend);
+    }
+    let backends = chain
+        .iter()
+        .map(|backend| Ok((config.backend_label(*backend), backend_from_config(config, *backend)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Box::new(Fallback::new(backends, Duration::from_secs(config.backend_timeout))))
+}
+
+/// Creates one backend using its section of `config`.
+pub fn backend_from_config(config: &Config, backend: LlmBackend) -> Result<Box<dyn LLMBackend>> {
+    Ok(match backend {
+        LlmBackend::OpenAI => {
+            let openai_config = config.openai.clone().unwrap_or_default();
+            let api_key = openai_config.resolve_api_key()?;
+            let engine = Engine::new(OpenAIBackend::new(api_key, Some(openai_config.endpoint), Some(openai_config.model)))
+                .with_prompts(config.prompts()?)
+                .with_thresholds(config.thresholds);
+            match config.redact.redactor(true)? {
+                Some(redactor) => Box::new(engine.with_redactor(redactor)),
+                None => Box::new(engine),
+            }
+        }
+        LlmBackend::Ollama => {
+            let ollama_config = config.ollama.clone().unwrap_or_default();
+            let transport = OllamaBackend::new(ollama_config.endpoint, Some(ollama_config.model))
+                .with_api(ollama_config.api)
+                .with_options(ollama_config.options)
+                .with_keep_alive(ollama_config.keep_alive);
+            let engine = Engine::new(transport)
+                .with_prompts(config.prompts()?)
+                .with_thresholds(config.thresholds);
+            match config.redact.redactor(false)? {
+                Some(redactor) => Box::new(engine.with_redactor(redactor)),
+                None => Box::new(engine),
+            }
+        }
+        LlmBackend::Mock => {
+            let mock_config = config.mock.as_ref().ok_or_else(|| anyhow!("Mock config missing"))?;
+            Box::new(MockBackend::from_file(&mock_config.fixtures)?.with_thresholds(config.thresholds))
+        }
+    })
+}
@@ -1,21 +1,25 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use anyhow::Result;
 use std::process::Command;
-use std::io::{self, Write};
-use crate::llm::ResponseType;
-mod config;
-mod llm;
-mod openai;
-mod ollama;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use toli::classify::{self, Risk};
+use toli::{config, llm, sandbox, script, undo, Ensemble, LLMBackend, ResponseType};
+use cli::models::retry_after_pull;
+mod cli;
 
 #[derive(Parser)]
 #[command(author, version, about = "A CLI tool that translates natural language queries into shell commands")]
-#[command(help_template = "{about-section}\n\nUsage: {usage}\n\n{options}\n\nExamples:\n  toli --how 'find all pdf files in current directory'\n  toli --do 'list all running docker containers'\n  toli 'show system memory usage'\n\nNote: By default, commands are displayed with explanations but not executed.")]
+#[command(help_template = "{about-section}\n\nUsage: {usage}\n\n{options}\n\nCommands:\n{subcommands}\n\nExamples:\n  toli --how 'find all pdf files in current directory'\n  toli --do 'list all running docker containers'\n  toli --sandbox 'delete all .tmp files here'\n  toli 'show system memory usage'\n  toli --breakdown 'tar -xzf backup.tgz -C /srv'\n  toli --explain --file deploy.sh\n  toli config init\n  toli eval queries.jsonl --profile quick --profile large\n  toli models pull llama3.2\n  toli usage --days 30\n  toli audit verify\n  toli undo\n\nNote: By default, commands are displayed with explanations but not executed.")]
 #[command(after_help = "Run 'howto --help' for more information about available options.")]
-#[command(arg_required_else_help = true)]
+#[command(arg_required_else_help = true, subcommand_negates_reqs = true)]
+#[command(override_usage = "toli [OPTIONS] <QUERY>...\n       toli [OPTIONS] <COMMAND>")]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// The query to translate into a shell command
-    #[arg(required = true, value_name = "QUERY")]
+    #[arg(required_unless_present = "file", value_name = "QUERY")]
     query: Vec<String>,
 
     /// Show command options without executing
@@ -28,45 +32,164 @@ struct Cli {
           help = "Execute the command after displaying it")]
     do_execute: bool,
 
+    /// Dry-run the selected command in a sandbox first
+    #[arg(long = "sandbox", default_value_t = false, conflicts_with_all = ["explain", "breakdown", "alias"],
+          help = "Run the selected command in a throwaway namespace against a copy of the current directory, without network, then ask before running it for real")]
+    sandbox: bool,
+
     /// Explain what a command does
     #[arg(short = 'e', long = "explain", default_value_t = false,
           help = "Explain what a given command does")]
     explain: bool,
 
+    /// Explain a script read from a file
+    #[arg(short = 'f', long = "file", value_name = "PATH", requires = "explain", conflicts_with = "query",
+          help = "Explain the script in PATH section by section, with a summary and its risky lines")]
+    file: Option<PathBuf>,
+
+    /// Explain a command word by word
+    #[arg(short = 'b', long = "breakdown", default_value_t = false, conflicts_with_all = ["alias", "do_execute"],
+          help = "Explain a command flag by flag, with each explanation under its word")]
+    breakdown: bool,
+
     /// Suggest aliases for a command
     #[arg(short = 'a', long = "alias", default_value_t = false,
           help = "Suggest aliases for a given command")]
     alias: bool,
+
+    /// Hide suggestions below this confidence
+    #[arg(long = "min-confidence", value_name = "0-1", value_parser = parse_confidence,
+          help = "Hide suggestions the model is less confident about than this (0 to 1)")]
+    min_confidence: Option<f64>,
+
+    /// Ask several models and merge their suggestions
+    #[arg(long = "ensemble", default_value_t = false, conflicts_with_all = ["explain", "breakdown", "alias"],
+          help = "Ask all configured backends (or the profiles listed in 'ensemble') at once and merge their suggestions")]
+    ensemble: bool,
+
+    /// Show token usage and timing
+    #[arg(short = 'v', long = "verbose", default_value_t = false,
+          help = "Print token usage, timing and estimated cost of each request")]
+    verbose: bool,
+
+    /// Configuration profile to use
+    #[arg(short = 'p', long = "profile", value_name = "NAME",
+          help = "Use the named [profiles.<NAME>] section of the config")]
+    profile: Option<String>,
+
+    /// Path to the configuration file
+    #[arg(short = 'c', long = "config", value_name = "FILE", global = true,
+          help = "Read the configuration from FILE instead of the default location")]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Create, inspect or change the configuration
+    Config {
+        #[command(subcommand)]
+        action: cli::config::ConfigAction,
+    },
+    /// Measure translation accuracy on a labelled dataset
+    Eval(cli::eval::EvalArgs),
+    /// Show token usage and estimated cost
+    Usage(cli::usage::UsageArgs),
+    /// Verify or export the log of executed commands
+    Audit {
+        #[command(subcommand)]
+        action: cli::audit::AuditAction,
+    },
+    /// Restore the files changed by the last command toli ran
+    Undo,
+    /// List or pull the models of the local Ollama server
+    Models {
+        #[command(subcommand)]
+        action: Option<cli::models::ModelsAction>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let query = cli.query.join(" ");
+    let config_path = config::get_config_file_path(cli.config.as_deref())?;
+    match cli.command {
+        Some(Commands::Config { action }) => return cli::config::run(action, &config_path).await,
+        Some(Commands::Eval(args)) => return cli::eval::run(args, &config_path).await,
+        Some(Commands::Usage(args)) => return cli::usage::run(args, &config_path).await,
+        Some(Commands::Models { action }) => return cli::models::run(action, &config_path).await,
+        Some(Commands::Audit { action }) => return cli::audit::run(action, &config_path).await,
+        Some(Commands::Undo) => return cli::undo::run(),
+        None => {}
+    }
+    // `-` reads the command from stdin, which keeps its quoting intact
+    let (query, source) = match &cli.file {
+        Some(path) => {
+            let script = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+            (script, path.display().to_string())
+        }
+        None if cli.query == ["-"] && (cli.explain || cli.breakdown) => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            (input, String::from("stdin"))
+        }
+        None => (cli.query.join(" "), String::new()),
+    };
+    let query = query.trim().to_string();
+    if query.is_empty() && !source.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to explain in {}", source));
+    }
 
     // Load configuration
-    let config = config::Config::load()?;
+    let mut config = config::Config::load(&config_path)?;
+    // Ensemble profiles are applied to the settings as loaded, not on top of --profile
+    let unprofiled = (!config.ensemble.is_empty()).then(|| config.clone());
+    config.apply_profile(cli.profile.as_deref())?;
+
+    cli::usage::check_budget(unprofiled.as_ref().filter(|_| cli.ensemble).unwrap_or(&config), cli.ensemble)?;
 
     // Initialize the appropriate LLM backend
-    let llm: Box<dyn llm::LLMBackend> = match config.backend {
-        config::LlmBackend::OpenAI => {
-            let openai_config = config.openai.ok_or_else(|| anyhow::anyhow!("OpenAI config missing"))?;
-            Box::new(openai::OpenAIBackend::new(openai_config.api_key, Some(openai_config.model)))
-        }
-        config::LlmBackend::Ollama => {
-            let ollama_config = config.ollama.ok_or_else(|| anyhow::anyhow!("Ollama config missing"))?;
-            Box::new(ollama::OllamaBackend::new(ollama_config.endpoint, Some(ollama_config.model)))
-        }
+    let (llm, ensemble_size): (Box<dyn LLMBackend>, usize) = if cli.ensemble {
+        let ensemble = Ensemble::from_config(unprofiled.as_ref().unwrap_or(&config))?;
+        let size = ensemble.size();
+        (Box::new(ensemble), size)
+    } else {
+        (llm::from_config(&config)?, 1)
     };
 
-    // Get command options from LLM
+    // Get command options from LLM, offering to pull a missing Ollama model first
+    let context = config.additional_context.as_str();
+    if cli.breakdown {
+        let breakdown = retry_after_pull(|| llm.explain_breakdown(&query, context)).await;
+        cli::usage::record(&llm.usage(), &config.usage, cli.verbose);
+        let breakdown = breakdown?;
+        let (risk, category) = classify::assess(&query);
+        println!("\n{}", breakdown.render());
+        println!("\nRisk: {} | Category: {}", risk, category);
+        return Ok(());
+    }
+
+    // Scripts are explained in sections, with a summary and the lines that look risky
+    if cli.explain && (cli.file.is_some() || query.contains('\n')) {
+        let explanation = retry_after_pull(|| script::explain(llm.as_ref(), &query, context)).await;
+        cli::usage::record(&llm.usage(), &config.usage, cli.verbose);
+        println!("\n{}", explanation?.render(&source));
+        return Ok(());
+    }
+
     let options = if cli.explain {
         // For explain flag, use the dedicated explain_command method
-        vec![llm.explain_command(&query, &config.additional_context).await?]
+        retry_after_pull(|| llm.explain_command(&query, context)).await.map(|explanation| vec![explanation])
     } else if cli.alias {
         // For alias flag, use the suggest_aliases method
-        match llm.suggest_aliases(&query, &config.additional_context).await {
+        let aliases = retry_after_pull(|| llm.suggest_aliases(&query, context)).await;
+        cli::usage::record(&llm.usage(), &config.usage, cli.verbose);
+        match aliases {
             Ok(aliases) => {
+                if aliases.is_empty() {
+                    println!("\nNo alias suggestions for '{}'.", query);
+                    return Ok(());
+                }
                 println!("\nSuggested aliases for '{}':"  , query);
                 for alias in aliases {
                     println!("\nAlias: {}", alias.command);
@@ -80,17 +203,31 @@ async fn main() -> Result<()> {
             }
         }
     } else {
-        llm.translate_to_command(&query, &config.additional_context).await?
+        retry_after_pull(|| llm.translate_to_command(&query, context)).await
     };
+    cli::usage::record(&llm.usage(), &config.usage, cli.verbose);
+    let options = options?;
+
+    // Hide low-confidence suggestions, but keep messages about unparseable replies
+    let min_confidence = cli.min_confidence.unwrap_or(config.thresholds.min_confidence);
+    let suggested = options.len();
+    let options: Vec<ResponseType> = options
+        .into_iter()
+        .filter(|option| option.is_parse_failure() || option.option().confidence >= min_confidence as f32)
+        .collect();
+    let hidden = suggested - options.len();
+    if options.is_empty() {
+        println!("\nNo suggestions with a confidence of at least {:.0}% ({} hidden).", min_confidence * 100.0, hidden);
+        return Ok(());
+    }
 
     // Display command options
     if cli.explain {
         // For explain flag, we only expect and show a single explanation
         if let Some(option) = options.first() {
             match option {
-                ResponseType::Command(cmd) => {
-                   
-                    println!("{}", cmd.explanation);
+                ResponseType::Command(suggestion) => {
+                    println!("{}", suggestion.option.explanation);
                 },
                 ResponseType::ScriptRecommended(_) | ResponseType::Uncertain(_) => {
                     return Err(anyhow::anyhow!("Unable to explain the command. Please check if the command is valid."));
@@ -101,25 +238,39 @@ async fn main() -> Result<()> {
         }
     } else {
         for (i, option) in options.iter().enumerate() {
-            println!("");
-            match option {
-                ResponseType::Command(cmd) => {
-                    println!("{}) {}", i + 1, cmd.command);
-                    println!("{}", cmd.explanation);
-                },
-                ResponseType::ScriptRecommended(cmd) => {
-                    println!("{}) {}", i + 1, cmd);
-                    println!("This command might need to be part of a script");
-                },
-                ResponseType::Uncertain(msg) => {
-                    println!("{}) Uncertain command", i + 1);
-                    println!("{}", msg);
-                }
+            println!();
+            let suggestion = option.suggestion();
+            if option.is_parse_failure() {
+                println!("{}) Uncertain command", i + 1);
+                println!("{}", suggestion.option.explanation);
+                continue;
             }
+            let label = if let ResponseType::Uncertain(_) = option { " (uncertain)" } else { "" };
+            println!("{}) {}{}", i + 1, suggestion.option.command, label);
+            println!("{}", suggestion.option.explanation);
+            if let ResponseType::ScriptRecommended(_) = option {
+                println!("This command might need to be part of a script");
+            }
+            let mut details = format!(
+                "Confidence: {:.0}% | Risk: {} | Category: {}",
+                suggestion.option.confidence * 100.0,
+                suggestion.risk,
+                suggestion.category
+            );
+            if !suggestion.sources.is_empty() {
+                details.push_str(&format!(" | From: {}", suggestion.sources.join(", ")));
+            }
+            println!("{}", details);
+            if ensemble_size > 1 && suggestion.risk == Risk::High && suggestion.sources.len() < ensemble_size {
+                println!("Warning: high-risk command suggested by only {} of {} models", suggestion.sources.len(), ensemble_size);
+            }
+        }
+        if hidden > 0 {
+            println!("\n({} more below {:.0}% confidence hidden)", hidden, min_confidence * 100.0);
         }
     }
 
-    let selected_command = if cli.do_execute {
+    let selected_command = if cli.do_execute || cli.sandbox {
         if options.len() > 1 {
             // Prompt user to select a command
             print!("\nSelect a command to execute (1-{}) or 0 to skip: ", options.len());
@@ -134,13 +285,9 @@ async fn main() -> Result<()> {
             if selection < 1 || selection > options.len() {
                 return Err(anyhow::anyhow!("Invalid selection"));
             }
-            match &options[selection - 1] {
-                ResponseType::Command(cmd) => &cmd.command,
-                ResponseType::ScriptRecommended(cmd) => cmd,
-                ResponseType::Uncertain(msg) => {
-                    return Err(anyhow::anyhow!("Cannot execute uncertain command: {}", msg));
-                }
-            }
+            executable(&options[selection - 1])?
+        } else if cli.sandbox {
+            executable(&options[0])?
         } else {
             print!("\nExecute this command? [Y/n]: ");
             io::stdout().flush()?;
@@ -150,13 +297,7 @@ async fn main() -> Result<()> {
                 println!("\nSkipping command execution.");
                 return Ok(());
             }
-            match &options[0] {
-                ResponseType::Command(cmd) => &cmd.command,
-                ResponseType::ScriptRecommended(cmd) => cmd,
-                ResponseType::Uncertain(msg) => {
-                    return Err(anyhow::anyhow!("Cannot execute uncertain command: {}", msg));
-                }
-            }
+            executable(&options[0])?
         }
     } else {
         return Ok(());
@@ -164,9 +305,51 @@ async fn main() -> Result<()> {
 
     // Execute the selected command
     let args = shell_words::split(selected_command)?;
+    if cli.sandbox {
+        let dry_run = sandbox::dry_run(&args, &std::env::current_dir()?)?;
+        println!("\n{}", dry_run.render());
+        print!("\nRun it for real? [y/N]: ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        if input.trim().to_lowercase() != "y" {
+            println!("\nSkipping command execution.");
+            return Ok(());
+        }
+    }
+    let audit = cli::audit::open(&config)?;
+    let cwd = std::env::current_dir()?;
+    let affected = undo::affected_paths(selected_command, &cwd);
+    if !affected.is_empty() {
+        match undo::Store::open().and_then(|store| store.save(selected_command, &cwd, &affected)) {
+            Ok(_) => eprintln!("Saved a snapshot of {} path{}; 'toli undo' restores it.", affected.len(), if affected.len() == 1 { "" } else { "s" }),
+            Err(e) => eprintln!("Warning: no undo snapshot taken: {}", e),
+        }
+    }
     let status = Command::new(&args[0])
         .args(&args[1..])
-        .status()?;
+        .status();
+    cli::audit::record(audit.as_ref(), &query, selected_command, llm.as_ref(), &status);
+    let status = status?;
 
     std::process::exit(status.code().unwrap_or(1));
-}
\ No newline at end of file
+}
+
+/// The command of an option the user chose to run; uncertain options are refused.
+fn executable(option: &ResponseType) -> Result<&str> {
+    option.command().ok_or_else(|| {
+        let suggestion = option.suggestion();
+        if option.is_parse_failure() {
+            anyhow::anyhow!("Cannot execute uncertain command: {}", suggestion.option.explanation)
+        } else {
+            anyhow::anyhow!("Cannot execute uncertain command: {}", suggestion.option.command)
+        }
+    })
+}
+
+fn parse_confidence(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(confidence) if (0.0..=1.0).contains(&confidence) => Ok(confidence),
+        _ => Err(String::from("expected a number between 0 and 1")),
+    }
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use crate::breakdown::{self, Breakdown};
+use crate::classify::Thresholds;
+use crate::llm::{LLMBackend, CommandOption, RawReply, ResponseType, Suggestion};
+
+/// Key that matches any query, command or alias request without its own entry.
+const WILDCARD: &str = "*";
+
+/// Canned responses keyed by the exact query or command, e.g.
+///
+/// ```json
+/// {
+///   "translate": { "list files": [{"command": "ls", "explanation": "Lists files", "confidence": 0.9}] },
+///   "explain": { "ls -la": "Lists all files in long format" },
+///   "breakdown": { "ls -la": {"summary": "Lists all files", "segments": [{"index": 1, "explanation": "list files"}]} },
+///   "summary": { "*": "Installs and starts the service" },
+///   "aliases": { "*": [] }
+/// }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Fixtures {
+    pub translate: HashMap<String, Vec<CommandOption>>,
+    pub explain: HashMap<String, String>,
+    /// Replies in the format the model is asked for; commands without one use `explain`.
+    pub breakdown: HashMap<String, serde_json::Value>,
+    /// Script summaries keyed by the explained sections; scripts without one get no summary.
+    pub summary: HashMap<String, String>,
+    pub aliases: HashMap<String, Vec<CommandOption>>,
+}
+
+/// A deterministic backend that answers from a fixtures file instead of a model.
+/// Used for tests and for trying out toli without a model available.
+pub struct MockBackend {
+    fixtures: Fixtures,
+    thresholds: Thresholds,
+    /// Translations handed out, as JSON.
+    replies: Mutex<Vec<RawReply>>,
+}
+
+impl MockBackend {
+    pub fn new(fixtures: Fixtures) -> Self {
+        Self { fixtures, thresholds: Thresholds::default(), replies: Mutex::default() }
+    }
+
+    /// Classifies suggestions with `thresholds` instead of the defaults.
+    pub fn with_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let fixtures_str = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read mock fixtures {}: {}", path.display(), e))?;
+        let fixtures = serde_json::from_str(&fixtures_str)
+            .map_err(|e| anyhow!("Failed to parse mock fixtures {}: {}", path.display(), e))?;
+        Ok(Self::new(fixtures))
+    }
+}
+
+fn lookup<'a, T>(entries: &'a HashMap<String, T>, key: &str, kind: &str) -> Result<&'a T> {
+    entries
+        .get(key)
+        .or_else(|| entries.get(WILDCARD))
+        .ok_or_else(|| anyhow!("No mock {} fixture for '{}'", kind, key))
+}
+
+#[async_trait]
+impl LLMBackend for MockBackend {
+    async fn translate_to_command(&self, query: &str, _additional_context: &str) -> Result<Vec<ResponseType>> {
+        let options = lookup(&self.fixtures.translate, query, "translate")?;
+        if options.is_empty() {
+            return Err(anyhow!("No valid command options generated"));
+        }
+        self.replies.lock().unwrap().push(RawReply {
+            backend: String::from("Mock"),
+            model: String::from("fixtures"),
+            task: String::from("translate"),
+            prompt_version: String::new(),
+            content: serde_json::to_string(options)?,
+        });
+        Ok(options.iter().cloned().map(|option| self.thresholds.classify(Suggestion::new(option))).collect())
+    }
+
+    async fn explain_command(&self, command: &str, _additional_context: &str) -> Result<ResponseType> {
+        let explanation = lookup(&self.fixtures.explain, command, "explain")?;
+        Ok(ResponseType::Command(Suggestion::new(CommandOption {
+            command: command.to_string(),
+            explanation: explanation.clone(),
+            confidence: 1.0
+        })))
+    }
+
+    async fn explain_breakdown(&self, command: &str, _additional_context: &str) -> Result<Breakdown> {
+        let Ok(reply) = lookup(&self.fixtures.breakdown, command, "breakdown") else {
+            let explanation = lookup(&self.fixtures.explain, command, "explain")?;
+            return Ok(Breakdown::unexplained(command, explanation.clone()));
+        };
+        let segments = breakdown::segments(command)
+            .ok_or_else(|| anyhow!("Could not split '{}' into words; check its quoting", command))?;
+        breakdown::parse_reply(command, &segments, &reply.to_string())
+            .ok_or_else(|| anyhow!("Invalid mock breakdown fixture for '{}'", command))
+    }
+
+    async fn suggest_aliases(&self, command: &str, _additional_context: &str) -> Result<Vec<CommandOption>> {
+        Ok(lookup(&self.fixtures.aliases, command, "aliases")?.clone())
+    }
+
+    async fn summarize_script(&self, sections: &str, _additional_context: &str) -> Result<String> {
+        Ok(lookup(&self.fixtures.summary, sections, "summary").cloned().unwrap_or_default())
+    }
+
+    fn replies(&self) -> Vec<RawReply> {
+        self.replies.lock().unwrap().clone()
+    }
+}
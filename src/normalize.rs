@@ -0,0 +1,272 @@
+use crate::shell::{
+    self, Command, CompoundCommand, Connector, List, Pipeline, Redirect, RedirectOp, RedirectTarget, SimpleCommand, Value, Word,
+};
+
+/// The short options of common programs that take a value, either the rest
+/// of their bundle (`-fout.tgz`) or the next word (`-f out.tgz`). Only the
+/// flags of these programs are reordered; others may mean something else in
+/// another order (`perl -pi` vs `perl -ip`), so they are left as written.
+const VALUE_FLAGS: &[(&str, &str)] = &[
+    ("cat", ""),
+    ("chgrp", ""),
+    ("chmod", ""),
+    ("chown", ""),
+    ("cp", "St"),
+    ("curl", "AbCcDdEeFHKmoQrTtUuwXxYyz"),
+    ("cut", "bcdf"),
+    ("df", "Bt"),
+    ("du", "Bdt"),
+    ("egrep", "ABCDdefm"),
+    ("grep", "ABCDdefm"),
+    ("head", "cn"),
+    ("install", "gmoSt"),
+    ("ln", "St"),
+    ("ls", "ITw"),
+    ("mkdir", "m"),
+    ("mv", "St"),
+    ("ps", "CGgopstUu"),
+    ("rm", ""),
+    ("rsync", "BefT"),
+    ("sed", "efl"),
+    ("shred", "ns"),
+    ("sort", "kSoTt"),
+    ("ssh", "BbcDEeFIiJLlmOopQRSWw"),
+    ("tail", "cns"),
+    ("tar", "bCFfgHKLNTVX"),
+    ("tee", ""),
+    ("touch", "dtr"),
+    ("truncate", "rs"),
+    ("uniq", "fsw"),
+    ("wc", ""),
+    ("xargs", "adEILnPs"),
+    ("zip", "bnt"),
+];
+
+/// The short flags of `program` that take a value, when it is a program
+/// whose flags are known.
+pub(crate) fn value_flags(program: &str) -> Option<&'static str> {
+    VALUE_FLAGS.iter().find(|(name, _)| *name == program).map(|(_, flags)| *flags)
+}
+
+/// Reduces a command to a canonical form so that commands differing only in
+/// quoting, whitespace or the order and bundling of adjacent flags compare
+/// equal, e.g. `ls -l -a "My Dir"` and `ls -al 'My Dir'`. The form is written
+/// out from the command's syntax tree.
+///
+/// Returns `None` when the command cannot be parsed (e.g. unbalanced quotes).
+pub fn canonical(command: &str) -> Option<String> {
+    shell::parse(command).ok().map(|list| list_form(&list))
+}
+
+/// Whether two commands are the same after canonicalization.
+pub fn equivalent(a: &str, b: &str) -> bool {
+    match (canonical(a), canonical(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.trim() == b.trim(),
+    }
+}
+
+fn list_form(list: &List) -> String {
+    let items: Vec<String> = list
+        .items
+        .iter()
+        .map(|item| {
+            let mut form = pipeline_form(&item.first);
+            for (connector, pipeline) in &item.rest {
+                form.push_str(match connector {
+                    Connector::And => " && ",
+                    Connector::Or => " || ",
+                });
+                form.push_str(&pipeline_form(pipeline));
+            }
+            if item.background {
+                form.push_str(" &");
+            }
+            form
+        })
+        .collect();
+    items.join("; ")
+}
+
+fn pipeline_form(pipeline: &Pipeline) -> String {
+    let commands: Vec<String> = pipeline.commands.iter().map(command_form).collect();
+    format!("{}{}", if pipeline.negated { "! " } else { "" }, commands.join(" | "))
+}
+
+fn command_form(command: &Command) -> String {
+    let mut parts: Vec<String> = match command {
+        Command::Simple(simple) => {
+            let mut parts: Vec<String> = simple
+                .assignments
+                .iter()
+                .map(|assignment| match &assignment.value {
+                    Value::Scalar(word) => format!("{}={}", assignment.name, word_form(word)),
+                    Value::Array(items) => format!("{}=({})", assignment.name, words_form(items)),
+                })
+                .collect();
+            parts.extend(simple_words_form(simple));
+            parts
+        }
+        Command::Compound(compound, _) => vec![compound_form(compound)],
+        Command::Function { name, body } => vec![format!("{}() {}", name, command_form(body))],
+    };
+    parts.extend(command.redirects().iter().map(redirect_form));
+    parts.join(" ")
+}
+
+fn compound_form(compound: &CompoundCommand) -> String {
+    match compound {
+        CompoundCommand::Group(body) => format!("{{ {}; }}", list_form(body)),
+        CompoundCommand::Subshell(body) => format!("( {} )", list_form(body)),
+        CompoundCommand::If { branches, otherwise } => {
+            let branches: Vec<String> = branches
+                .iter()
+                .map(|(condition, body)| format!("{}; then {}", list_form(condition), list_form(body)))
+                .collect();
+            let otherwise = otherwise.as_ref().map(|body| format!("; else {}", list_form(body))).unwrap_or_default();
+            format!("if {}{}; fi", branches.join("; elif "), otherwise)
+        }
+        CompoundCommand::While { until, condition, body } => {
+            format!("{} {}; do {}; done", if *until { "until" } else { "while" }, list_form(condition), list_form(body))
+        }
+        CompoundCommand::For { select, variable, words, body } => {
+            let words = words.as_ref().map(|words| format!(" in {}", words_form(words))).unwrap_or_default();
+            format!("{} {}{}; do {}; done", if *select { "select" } else { "for" }, variable, words, list_form(body))
+        }
+        CompoundCommand::ArithmeticFor { header, body } => {
+            format!("for (({})); do {}; done", collapse(header), list_form(body))
+        }
+        CompoundCommand::Case { word, arms } => {
+            let arms: Vec<String> = arms
+                .iter()
+                .map(|arm| {
+                    let patterns: Vec<String> = arm.patterns.iter().map(word_form).collect();
+                    format!("{}) {};;", patterns.join(" | "), list_form(&arm.body))
+                })
+                .collect();
+            format!("case {} in {} esac", word_form(word), arms.join(" "))
+        }
+        CompoundCommand::Conditional(words) => format!("[[ {} ]]", words_form(words)),
+        CompoundCommand::Arithmetic(expression) => format!("(( {} ))", collapse(expression)),
+    }
+}
+
+fn redirect_form(redirect: &Redirect) -> String {
+    let operator = match redirect.op {
+        RedirectOp::Input => "<",
+        RedirectOp::Output => ">",
+        RedirectOp::Append => ">>",
+        RedirectOp::Clobber => ">|",
+        RedirectOp::ReadWrite => "<>",
+        RedirectOp::DupInput => "<&",
+        RedirectOp::DupOutput => ">&",
+        RedirectOp::OutputAndError => "&>",
+        RedirectOp::AppendOutputAndError => "&>>",
+        RedirectOp::HereDoc => "<<",
+        RedirectOp::HereString => "<<<",
+    };
+    let fd = redirect.fd.map(|fd| fd.to_string()).unwrap_or_default();
+    let target = match &redirect.target {
+        RedirectTarget::Word(word) => word_form(word),
+        RedirectTarget::HereDoc(heredoc) => shell_words::quote(&heredoc.body).into_owned(),
+    };
+    format!("{}{}{}", fd, operator, target)
+}
+
+fn words_form(words: &[Word]) -> String {
+    words.iter().map(word_form).collect::<Vec<_>>().join(" ")
+}
+
+/// The words of a simple command. For a program in [`VALUE_FLAGS`], each run
+/// of flags after it is merged as far as that can't change what they mean.
+fn simple_words_form(command: &SimpleCommand) -> Vec<String> {
+    let values: Vec<String> = command.words.iter().map(Word::value).collect();
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+    let start = shell::wrapped_program(&values);
+    let Some(program) = values.get(start).map(|p| p.rsplit('/').next().unwrap_or(p)) else {
+        return command.words.iter().map(word_form).collect();
+    };
+    let Some(value_flags) = value_flags(program) else {
+        return command.words.iter().map(word_form).collect();
+    };
+
+    let mut form: Vec<String> = command.words[..=start].iter().map(word_form).collect();
+    let mut flags: Vec<String> = Vec::new();
+    let mut options_done = false;
+    for word in &command.words[start + 1..] {
+        match word.literal().filter(|value| !options_done && is_flag(value)) {
+            Some(flag) => flags.push(flag),
+            None => {
+                form.extend(merge_flags(&mut flags, value_flags, true));
+                options_done |= word.literal().as_deref() == Some("--");
+                form.push(word_form(word));
+            }
+        }
+    }
+    form.extend(merge_flags(&mut flags, value_flags, false));
+    form
+}
+
+/// A word quoted only as needed; words with expansions are double-quoted.
+fn word_form(word: &Word) -> String {
+    match word.literal() {
+        Some(value) => shell_words::quote(&value).into_owned(),
+        None => format!("\"{}\"", word.value().replace('"', "\\\"")),
+    }
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_flag(token: &str) -> bool {
+    token.len() > 1 && token.starts_with('-') && token != "--" && !token.contains('=')
+}
+
+/// Combines a run of adjacent flags so their order and bundling do not
+/// matter: value-less short flags are merged into one sorted bundle and the
+/// other flags sorted. A short flag taking a value keeps the rest of its
+/// bundle, and the flag that may take the argument after the run (given
+/// `before_argument`) stays last, so `tar -czf out` and `tar -cfz out` differ.
+/// `value_flags` lists the short flags taking a value.
+fn merge_flags(flags: &mut Vec<String>, value_flags: &str, before_argument: bool) -> Vec<String> {
+    let mut short: Vec<char> = Vec::new();
+    let mut other: Vec<String> = Vec::new();
+    let mut last = None;
+    let count = flags.len();
+    for (i, flag) in flags.drain(..).enumerate() {
+        let is_last = before_argument && i + 1 == count;
+        if flag.starts_with("--") {
+            if is_last {
+                last = Some(flag);
+            } else {
+                other.push(flag);
+            }
+            continue;
+        }
+        let letters: Vec<char> = flag[1..].chars().collect();
+        match letters.iter().position(|c| value_flags.contains(*c)) {
+            Some(at) => {
+                short.extend(&letters[..at]);
+                let rest = format!("-{}", letters[at..].iter().collect::<String>());
+                if is_last && at + 1 == letters.len() {
+                    last = Some(rest);
+                } else {
+                    other.push(rest);
+                }
+            }
+            None => short.extend(letters),
+        }
+    }
+    short.sort_unstable();
+    short.dedup();
+    other.sort();
+
+    let mut merged = Vec::new();
+    if !short.is_empty() {
+        merged.push(format!("-{}", short.into_iter().collect::<String>()));
+    }
+    merged.extend(other);
+    merged.extend(last);
+    merged
+}
@@ -1,12 +1,172 @@
 use async_trait::async_trait;
 use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::io::Write;
-use crate::llm::{LLMBackend, CommandOption, ResponseType};
+use crate::engine::{ConnectionError, Message, Reply, Transport};
+use crate::prompts::Task;
+use crate::usage::Usage;
+
+/// The requested model hasn't been pulled on the Ollama server.
+#[derive(Debug)]
+pub struct ModelNotFound {
+    pub endpoint: String,
+    pub model: String,
+}
+
+impl std::fmt::Display for ModelNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Model '{}' is not available in Ollama at {} (pull it with 'toli models pull {}')",
+            self.model, self.endpoint, self.model
+        )
+    }
+}
+
+impl std::error::Error for ModelNotFound {}
+
+/// A model installed on an Ollama server, as listed by `/api/tags`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalModel {
+    pub name: String,
+    /// Size on disk in bytes.
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// Details of an installed model from `/api/show`.
+#[derive(Debug, Clone, Default)]
+pub struct ModelDetails {
+    pub context_length: Option<u64>,
+    pub parameter_size: Option<String>,
+    pub quantization: Option<String>,
+}
+
+/// A progress update from `/api/pull`. `total` is 0 while no download is running.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default)]
+    pub completed: u64,
+    #[serde(default)]
+    pub total: u64,
+}
+
+/// Sampling and runtime parameters sent as Ollama's `options`. Unset values
+/// are left to the model's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GenerationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Context window in tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Maximum number of tokens to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+impl GenerationOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These options with every value set in `overrides` replaced.
+    pub fn merged(&self, overrides: &Self) -> Self {
+        Self {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            num_ctx: overrides.num_ctx.or(self.num_ctx),
+            seed: overrides.seed.or(self.seed),
+            num_predict: overrides.num_predict.or(self.num_predict),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+        }
+    }
+}
+
+/// Options for every request, with overrides per task:
+///
+/// ```toml
+/// [ollama.options]
+/// num_ctx = 8192
+///
+/// [ollama.options.translate]
+/// temperature = 0
+/// seed = 42
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OllamaOptions {
+    #[serde(flatten)]
+    pub common: GenerationOptions,
+    #[serde(skip_serializing_if = "GenerationOptions::is_empty")]
+    pub translate: GenerationOptions,
+    #[serde(skip_serializing_if = "GenerationOptions::is_empty")]
+    pub explain: GenerationOptions,
+    #[serde(skip_serializing_if = "GenerationOptions::is_empty")]
+    pub aliases: GenerationOptions,
+}
+
+impl OllamaOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The options used for requests made for `task`.
+    pub fn for_task(&self, task: Task) -> GenerationOptions {
+        let overrides = match task {
+            Task::Translate => &self.translate,
+            Task::Explain | Task::Breakdown | Task::Summary => &self.explain,
+            Task::Aliases => &self.aliases,
+        };
+        self.common.merged(overrides)
+    }
+}
+
+/// The Ollama endpoint requests are sent to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OllamaApi {
+    /// `/api/chat`, with toli's instructions in a system message.
+    #[default]
+    Chat,
+    /// `/api/generate` with everything in a single prompt, for older servers.
+    Generate,
+}
+
+impl OllamaApi {
+    pub fn is_chat(&self) -> bool {
+        *self == OllamaApi::Chat
+    }
+}
+
+/// How long Ollama keeps the model loaded after a request: a duration such as
+/// `"10m"`, or a number of seconds where a negative value means forever.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum KeepAlive {
+    Seconds(i64),
+    Duration(String),
+}
+
+/// Ollama reports `llama3.2:latest` for a model configured as `llama3.2`.
+pub fn model_matches(available: &str, configured: &str) -> bool {
+    available == configured || available.strip_suffix(":latest") == Some(configured)
+}
 
 pub struct OllamaBackend {
     endpoint: String,
     model: String,
+    api: OllamaApi,
+    options: OllamaOptions,
+    keep_alive: Option<KeepAlive>,
 }
 
 impl OllamaBackend {
@@ -14,232 +174,210 @@ impl OllamaBackend {
         Self {
             endpoint,
             model: model.unwrap_or_else(|| String::from("llama2")),
+            api: OllamaApi::default(),
+            options: OllamaOptions::default(),
+            keep_alive: None,
         }
     }
-}
 
-#[async_trait]
-impl LLMBackend for OllamaBackend {
-    async fn translate_to_command(&self, query: &str, additional_context: &str) -> Result<Vec<ResponseType>> {
-        let mut attempts = 0;
-        let max_attempts = 5;
-        let mut failed_responses = Vec::new();
-        let feedback = [".   ", "..  ", "... ", "...."];
-
-        while attempts < max_attempts {
-            if attempts > 0 {
-                print!("\rThinking{}", feedback[attempts % feedback.len()]);
-                std::io::stdout().flush().ok();
-            }
-
-            let client = reqwest::Client::new();
-            let mut prompt = format!(
-                "You are a command-line assistant. Your task is to translate user queries into appropriate shell commands or recommend to use scripts for complex tasks. Details about user's environment: {}. RESPOND ONLY WITH A VALID JSON ARRAY OF OPTIONS.\n\nIMPORTANT: Only suggest commands for operations that can be completed in a single shot, piping is okay. For any task requiring multiple steps, dependencies, or complex setup, recommend to use a script instead.\n\nEach command option must have these fields:\n- 'command': For single-shot tasks: the exact shell command. For complex tasks: a suggested script name\n- 'explanation': A brief description of what the command does and why it's recommended, for scripts an high level description of what it should do\n- 'confidence': A float between 0 and 1:\n  - >= 0.8 ONLY for simple, direct commands that can be executed in one shot\n  - 0.5-0.7 for tasks requiring scripts (multiple steps, dependencies, or complex setup)\n  - < 0.5 for uncertain suggestions\n\nExample response format:\n[{{\"command\": \"#!/bin/bash\necho 'Installing Docker...'\nbrew install docker\nbrew install docker-compose\", \"explanation\": \"Script recommended: Docker installation requires multiple steps and dependency management\", \"confidence\": 0.6}}]\n\nProvide up to 5 options. DO NOT include any text before or after the JSON array. Here's the query: {}",
-                additional_context,
-                query
-            );
-
-            if !failed_responses.is_empty() {
-                prompt.push_str("\n\nPrevious attempts failed to generate valid JSON. Here are the failed responses:\n");
-                for (i, response) in failed_responses.iter().enumerate() {
-                    prompt.push_str(&format!("\nAttempt {}: {}\n", i + 1, response));
-                }
-                prompt.push_str("\nPlease ensure your response is a valid JSON array.");
-            }
+    /// Sends requests to `api` instead of `/api/chat`.
+    pub fn with_api(mut self, api: OllamaApi) -> Self {
+        self.api = api;
+        self
+    }
 
-            let response = client
-                .post(format!("{}/api/generate", self.endpoint))
-                .json(&serde_json::json!({
-                    "model": self.model,
-                    "prompt": prompt,
-                    "stream": false
-                }))
-                .send()
-                .await
-                .map_err(|e| anyhow!("Failed to send request: {}", e))?;
+    /// Sends `options` with each request, picking the task's overrides.
+    pub fn with_options(mut self, options: OllamaOptions) -> Self {
+        self.options = options;
+        self
+    }
 
-            if !response.status().is_success() {
-                return Err(anyhow!("API request failed with status: {}", response.status()));
-            }
+    /// Asks Ollama to keep the model loaded for `keep_alive` after each request.
+    pub fn with_keep_alive(mut self, keep_alive: Option<KeepAlive>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
 
-            let response_text = response.text().await
-                .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+    /// Returns the names of the models available on the Ollama server at `endpoint`.
+    pub async fn list_local_models(endpoint: &str) -> Result<Vec<String>> {
+        Ok(Self::local_models(endpoint).await?.into_iter().map(|m| m.name).collect())
+    }
 
-            let response_data: Value = serde_json::from_str(&response_text)
-                .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
-
-            let response_str = response_data["response"]
-                .as_str()
-                .ok_or_else(|| anyhow!("Invalid response format"))?;
-
-            let parsed_result = serde_json::from_str::<Vec<CommandOption>>(response_str);
-            match parsed_result {
-                Ok(options) => {
-                    if options.is_empty() {
-                        failed_responses.push(response_str.to_string());
-                    } else {
-                        let responses: Vec<ResponseType> = options.into_iter()
-                            .map(|opt| {
-                                if opt.confidence >= 0.8 {
-                                    ResponseType::Command(opt)
-                                } else if opt.confidence >= 0.5 {
-                                    ResponseType::ScriptRecommended(opt.command)
-                                } else {
-                                    ResponseType::Uncertain(format!("Uncertain about command: {}", opt.command))
-                                }
-                            })
-                            .collect();
-                        return Ok(responses);
-                    }
-                }
-                Err(_) => {
-                    failed_responses.push(response_str.to_string());
-                }
-            }
+    /// Returns the models available on the Ollama server at `endpoint`.
+    pub async fn local_models(endpoint: &str) -> Result<Vec<LocalModel>> {
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("{}/api/tags", endpoint.trim_end_matches('/')))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to reach Ollama at {}: {}", endpoint, e))?;
 
-            attempts += 1;
+        if !response.status().is_success() {
+            return Err(anyhow!("API request failed with status: {}", response.status()));
         }
 
-        Ok(vec![ResponseType::Uncertain(String::from(
-            "Failed to generate valid command options after multiple attempts."
-        ))])
+        let response_data: Value = response.json().await
+            .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
+
+        let models = response_data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response format"))?
+            .iter()
+            .filter_map(|m| serde_json::from_value(m.clone()).ok())
+            .collect();
+
+        Ok(models)
     }
 
-    async fn suggest_aliases(&self, command: &str, additional_context: &str) -> Result<Vec<CommandOption>> {
-        let mut attempts = 0;
-        let max_attempts = 5;
-        let mut failed_responses = Vec::new();
-        let feedback = [".   ", "..  ", "... ", "...."];
+    /// Returns details of `model`, or `None` when it hasn't been pulled.
+    pub async fn show_model(endpoint: &str, model: &str) -> Result<Option<ModelDetails>> {
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/api/show", endpoint.trim_end_matches('/')))
+            .json(&serde_json::json!({ "model": model }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to reach Ollama at {}: {}", endpoint, e))?;
 
-        while attempts < max_attempts {
-            if attempts > 0 {
-                print!("\rThinking{}", feedback[attempts % feedback.len()]);
-                std::io::stdout().flush().ok();
-            }
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!("API request failed with status: {}", response.status()));
+        }
 
-            let client = reqwest::Client::new();
-            let mut prompt = format!(
-                "You are a command-line expert. \
-                Only For the command '{}', suggest (up to 3) useful aliases that would make working with this command more efficient.\n\n\
-                Don't suggest alias for other commands. Only the one provided earlier. \
-                Consider the following context about the user's environment, but only when applicable: {}. \
-                IMPORTANT: Your response must be a valid JSON array containing objects with exactly these fields:\n\
-                - 'command' (string with alias definition)\n\
-                - 'explanation' (string describing what it does)\n\
-                - 'confidence' (number between 0 and 1)\n\n\
-                Example response: [{{\
-                \"command\": \"alias ll='ls -la'\", \
-                \"explanation\": \"Lists all files in long format\", \
-                \"confidence\": 1.0\
-                }}]. \
-                Ensure proper JSON escaping and shell syntax conventions. \
-                RESPOND ONLY IN JSON. DO NOT INCLUDE ANYTHING ELSE BESIDE JSON.",
-                command,
-                additional_context
-            );
-
-            if !failed_responses.is_empty() {
-                prompt.push_str("\n\nPrevious attempts failed to generate valid JSON. Here are the failed responses:\n");
-                for (i, response) in failed_responses.iter().enumerate() {
-                    prompt.push_str(&format!("\nAttempt {}: {}\n", i + 1, response));
-                }
-                prompt.push_str("\nPlease ensure your response is a valid JSON array.");
-            }
+        let response_data: Value = response.json().await
+            .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
 
-            let response = client
-                .post(format!("{}/api/generate", self.endpoint))
-                .json(&serde_json::json!({
-                    "model": self.model,
-                    "prompt": prompt,
-                    "stream": false
-                }))
-                .send()
-                .await
-                .map_err(|e| anyhow!("Failed to send request: {}", e))?;
+        // The context length is reported under an architecture prefix, e.g. `llama.context_length`.
+        let context_length = response_data["model_info"]
+            .as_object()
+            .and_then(|info| info.iter().find(|(key, _)| key.ends_with(".context_length")))
+            .and_then(|(_, value)| value.as_u64());
+        let details = &response_data["details"];
+        Ok(Some(ModelDetails {
+            context_length,
+            parameter_size: details["parameter_size"].as_str().map(String::from),
+            quantization: details["quantization_level"].as_str().map(String::from),
+        }))
+    }
 
-            if !response.status().is_success() {
-                return Err(anyhow!("API request failed with status: {}", response.status()));
-            }
+    /// Downloads `model` to the Ollama server, reporting progress as it goes.
+    pub async fn pull_model(endpoint: &str, model: &str, mut on_progress: impl FnMut(&PullProgress)) -> Result<()> {
+        let client = reqwest::Client::new();
+        let mut response = client
+            .post(format!("{}/api/pull", endpoint.trim_end_matches('/')))
+            .json(&serde_json::json!({ "model": model, "stream": true }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to reach Ollama at {}: {}", endpoint, e))?;
 
-            let response_text = response.text().await
-                .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+        if !response.status().is_success() {
+            return Err(anyhow!("API request failed with status: {}", response.status()));
+        }
 
-            let response_data: Value = serde_json::from_str(&response_text)
-                .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
-
-            let response_str = response_data["response"]
-                .as_str()
-                .ok_or_else(|| anyhow!("Invalid response format"))?;
-
-            // Clean up the response string to handle escaping issues
-            let cleaned_response = response_str
-                .trim()
-                .replace("\\'", "'")
-                .replace("\\\"", "\"")
-                .replace("\\\\", "\\")
-                .replace("\n", "")
-                .replace("\r", "");
-
-            //println!("Attempt {}: {}", attempts + 1, cleaned_response);
-
-            // Try parsing with serde_json first
-            let parse_result = serde_json::from_str::<Vec<CommandOption>>(&cleaned_response);
-            
-            // If that fails, try to extract JSON array from the response
-            let parse_result = if parse_result.is_err() {
-                if let Some(start) = cleaned_response.find('[') {
-                    if let Some(end) = cleaned_response.rfind(']') {
-                        let json_str = &cleaned_response[start..=end];
-                        serde_json::from_str::<Vec<CommandOption>>(json_str)
-                    } else {
-                        parse_result
-                    }
-                } else {
-                    parse_result
-                }
-            } else {
-                parse_result
-            };
-
-            match parse_result {
-                Ok(options) => {
-                    if options.is_empty() {
-                        failed_responses.push(cleaned_response.to_string());
-                    } else {
-                        return Ok(options);
-                    }
-                }
-                Err(e) => {
-                    //println!("Failed to parse JSON: {}", e);
-                    failed_responses.push(cleaned_response.to_string());
+        // Progress arrives as one JSON object per line.
+        let mut buffer = Vec::new();
+        let mut succeeded = false;
+        loop {
+            let chunk = response.chunk().await
+                .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+            match &chunk {
+                Some(bytes) => buffer.extend_from_slice(bytes),
+                None => buffer.push(b'\n'),
+            }
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let update: Value = match serde_json::from_slice(line.trim_ascii()) {
+                    Ok(update) => update,
+                    Err(_) => continue,
+                };
+                if let Some(error) = update["error"].as_str() {
+                    return Err(anyhow!("Failed to pull '{}': {}", model, error));
                 }
+                let progress: PullProgress = serde_json::from_value(update).unwrap_or_default();
+                succeeded |= progress.status == "success";
+                on_progress(&progress);
             }
+            if chunk.is_none() {
+                break;
+            }
+        }
 
-            attempts += 1;
+        if !succeeded {
+            return Err(anyhow!("Pulling '{}' ended before it completed", model));
         }
+        Ok(())
+    }
+}
 
-        Ok(vec![])
+#[async_trait]
+impl Transport for OllamaBackend {
+    async fn chat(&self, messages: &[Message]) -> Result<Reply> {
+        self.send(messages, &self.options.common).await
     }
 
-    async fn explain_command(&self, command: &str, additional_context: &str) -> Result<ResponseType> {
-        let client = reqwest::Client::new();
-        let prompt = format!(
-            "You are a command-line expert. Explain briefly what this command does: '{}'. Consider the following context about the user's environment: {}. \
-             Keep it brief to a paragraph.",
-            command, additional_context
-        );
+    async fn chat_for(&self, task: Task, messages: &[Message]) -> Result<Reply> {
+        self.send(messages, &self.options.for_task(task)).await
+    }
 
-        let response = client
-            .post(format!("{}/api/generate", self.endpoint))
-            .json(&serde_json::json!({
+    fn describe(&self) -> (&str, &str) {
+        ("Ollama", &self.model)
+    }
+}
+
+impl OllamaBackend {
+    async fn send(&self, messages: &[Message], options: &GenerationOptions) -> Result<Reply> {
+        let (path, mut request) = match self.api {
+            OllamaApi::Chat => ("/api/chat", serde_json::json!({
                 "model": self.model,
-                "prompt": prompt,
+                "messages": messages,
                 "stream": false
-            }))
+            })),
+            OllamaApi::Generate => {
+                // /api/generate takes a single prompt, so the turns are sent as one text.
+                let prompt = messages
+                    .iter()
+                    .map(|message| message.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                ("/api/generate", serde_json::json!({
+                    "model": self.model,
+                    "prompt": prompt,
+                    "stream": false
+                }))
+            }
+        };
+        if !options.is_empty() {
+            request["options"] = serde_json::to_value(options)?;
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            request["keep_alive"] = serde_json::to_value(keep_alive)?;
+        }
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}{}", self.endpoint.trim_end_matches('/'), path))
+            .json(&request)
             .send()
             .await
-            .map_err(|e| anyhow!("Failed to send request: {}", e))?;
-
+            .map_err(|e| ConnectionError(format!("Failed to send request: {}", e)))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            // A missing model is reported as JSON; a server without the endpoint answers in plain text.
+            let body = response.text().await.unwrap_or_default();
+            let error = serde_json::from_str::<Value>(&body).ok().and_then(|data| data["error"].as_str().map(String::from));
+            return Err(match error {
+                Some(error) if error.contains("not found") => {
+                    ModelNotFound { endpoint: self.endpoint.clone(), model: self.model.clone() }.into()
+                }
+                _ if self.api == OllamaApi::Chat => anyhow!(
+                    "Ollama at {} does not support /api/chat; upgrade it or set ollama.api = \"generate\"",
+                    self.endpoint
+                ),
+                _ => anyhow!("API request failed with status: {}", reqwest::StatusCode::NOT_FOUND),
+            });
+        }
         if !response.status().is_success() {
             return Err(anyhow!("API request failed with status: {}", response.status()));
         }
@@ -250,14 +388,24 @@ impl LLMBackend for OllamaBackend {
         let response_data: Value = serde_json::from_str(&response_text)
             .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
 
-        let explanation = response_data["response"]
+        let reply = match self.api {
+            OllamaApi::Chat => &response_data["message"]["content"],
+            OllamaApi::Generate => &response_data["response"],
+        };
+        let content = reply
             .as_str()
+            .map(String::from)
             .ok_or_else(|| anyhow!("Invalid response format"))?;
-
-        Ok(ResponseType::Command(CommandOption {
-            command: command.to_string(),
-            explanation: explanation.to_string(),
-            confidence: 1.0
-        }))
+        // Counts are left out of the final message when nothing was evaluated, e.g. a cached prompt.
+        let millis = |field: &str| response_data[field].as_u64().map(|nanos| nanos / 1_000_000);
+        let usage = response_data["eval_count"].as_u64().map(|completion_tokens| Usage {
+            prompt_tokens: response_data["prompt_eval_count"].as_u64().unwrap_or_default(),
+            completion_tokens,
+            load_ms: millis("load_duration"),
+            prompt_eval_ms: millis("prompt_eval_duration"),
+            eval_ms: millis("eval_duration"),
+            total_ms: millis("total_duration"),
+        });
+        Ok(Reply { content, usage })
     }
-}
\ No newline at end of file
+}
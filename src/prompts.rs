@@ -0,0 +1,212 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::config;
+
+/// Bumped whenever the built-in templates change meaningfully.
+const BUILTIN_VERSION: &str = "v1";
+
+/// How many options the model is asked for.
+pub const MAX_OPTIONS: usize = 3;
+
+/// Built-in templates, overridable by files of the same name in the prompts directory.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("translate.system", include_str!("prompts/translate.system.txt")),
+    ("translate.user", include_str!("prompts/translate.user.txt")),
+    ("explain.system", include_str!("prompts/explain.system.txt")),
+    ("explain.user", include_str!("prompts/explain.user.txt")),
+    ("breakdown.system", include_str!("prompts/breakdown.system.txt")),
+    ("breakdown.user", include_str!("prompts/breakdown.user.txt")),
+    ("summary.system", include_str!("prompts/summary.system.txt")),
+    ("summary.user", include_str!("prompts/summary.user.txt")),
+    ("aliases.system", include_str!("prompts/aliases.system.txt")),
+    ("aliases.user", include_str!("prompts/aliases.user.txt")),
+    ("examples", include_str!("prompts/examples.txt")),
+];
+
+/// The tasks toli prompts a model for. Each has a `system` and a `user` template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Translate,
+    Explain,
+    /// Explaining a command word by word.
+    Breakdown,
+    /// Summarizing a script from the explanations of its sections.
+    Summary,
+    Aliases,
+}
+
+impl Task {
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Translate => "translate",
+            Task::Explain => "explain",
+            Task::Breakdown => "breakdown",
+            Task::Summary => "summary",
+            Task::Aliases => "aliases",
+        }
+    }
+}
+
+/// A rendered prompt: instructions for the system role and the user's request.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub system: String,
+    pub user: String,
+}
+
+/// Prompt templates with `{{variable}}` placeholders. Available variables are
+/// `query` (translate), `command` (explain, breakdown, aliases), `segments`
+/// (breakdown), `sections` (summary), `context`, `shell`, `examples` and `max_options`.
+#[derive(Debug, Clone)]
+pub struct Prompts {
+    templates: HashMap<String, String>,
+    /// Files that replaced a built-in template, by template name.
+    overrides: HashMap<String, PathBuf>,
+    version: String,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Prompts {
+    pub fn builtin() -> Self {
+        let templates: HashMap<String, String> = BUILTIN_TEMPLATES
+            .iter()
+            .map(|(name, template)| (name.to_string(), template.to_string()))
+            .collect();
+        let version = version_of(&templates, false);
+        Self { templates, overrides: HashMap::new(), version }
+    }
+
+    /// Loads the built-in templates, overridden by `<dir>/<name>.txt` and then by
+    /// `<dir>/<profile>/<name>.txt` when a profile is active.
+    pub fn load(dir: &Path, profile: Option<&str>) -> Result<Self> {
+        let mut prompts = Self::builtin();
+        let mut search_dirs = vec![dir.to_path_buf()];
+        if let Some(profile) = profile {
+            search_dirs.push(dir.join(profile));
+        }
+
+        for search_dir in &search_dirs {
+            for (name, _) in BUILTIN_TEMPLATES {
+                let path = search_dir.join(format!("{}.txt", name));
+                if path.is_file() {
+                    let template = std::fs::read_to_string(&path)
+                        .map_err(|e| anyhow!("Failed to read prompt template {}: {}", path.display(), e))?;
+                    prompts.templates.insert(name.to_string(), template);
+                    prompts.overrides.insert(name.to_string(), path);
+                }
+            }
+        }
+
+        if !prompts.overrides.is_empty() {
+            prompts.version = version_of(&prompts.templates, true);
+        }
+        Ok(prompts)
+    }
+
+    /// Identifies the template set, e.g. `v1` for the built-ins or `v1+custom.3f9a12c4`
+    /// when templates were overridden, so results can be traced to the prompts used.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Template names with the file each was loaded from, or `None` for built-ins.
+    pub fn sources(&self) -> Vec<(&'static str, Option<&Path>)> {
+        BUILTIN_TEMPLATES
+            .iter()
+            .map(|(name, _)| (*name, self.overrides.get(*name).map(PathBuf::as_path)))
+            .collect()
+    }
+
+    /// Writes the built-in templates to `dir` as a starting point for
+    /// customisation, skipping files that already exist. Returns the files written.
+    pub fn export_builtin(dir: &Path) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        for (name, template) in BUILTIN_TEMPLATES {
+            let path = dir.join(format!("{}.txt", name));
+            if !path.exists() {
+                std::fs::write(&path, template)?;
+                written.push(path);
+            }
+        }
+        Ok(written)
+    }
+
+    /// Renders the templates for `task`; `input` is the query, the command or
+    /// the explained sections of a script.
+    pub fn render(&self, task: Task, input: &str, context: &str) -> Result<Prompt> {
+        self.render_with(task, input, context, &[])
+    }
+
+    /// Like `render`, with task-specific variables such as `segments`.
+    pub fn render_with(&self, task: Task, input: &str, context: &str, extra: &[(&str, &str)]) -> Result<Prompt> {
+        let shell = config::detect_shell();
+        let examples = self.templates["examples"].trim().to_string();
+        let max_options = MAX_OPTIONS.to_string();
+        let input_name = match task {
+            Task::Translate => "query",
+            Task::Summary => "sections",
+            _ => "command",
+        };
+        let mut vars: Vec<(&str, &str)> = vec![
+            (input_name, input),
+            ("context", context),
+            ("shell", &shell),
+            ("examples", &examples),
+            ("max_options", &max_options),
+        ];
+        vars.extend_from_slice(extra);
+
+        let render = |part: &str| {
+            let name = format!("{}.{}", task.name(), part);
+            substitute(&self.templates[&name], &vars).map_err(|e| anyhow!("In prompt template '{}': {}", name, e))
+        };
+        Ok(Prompt { system: render("system")?, user: render("user")? })
+    }
+}
+
+/// Replaces `{{name}}` placeholders; unknown names are an error so typos in
+/// user templates don't silently reach the model.
+fn substitute(template: &str, vars: &[(&str, &str)]) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("unclosed '{{{{'"))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = vars
+            .iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| anyhow!("unknown variable '{{{{{}}}}}'", name))?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered.trim().to_string())
+}
+
+fn version_of(templates: &HashMap<String, String>, overridden: bool) -> String {
+    if !overridden {
+        return String::from(BUILTIN_VERSION);
+    }
+    // FNV-1a over the templates in a stable order.
+    let mut names: Vec<&String> = templates.keys().collect();
+    names.sort();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for name in names {
+        for byte in name.bytes().chain(templates[name].bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{}+custom.{:08x}", BUILTIN_VERSION, hash as u32)
+}
@@ -0,0 +1,163 @@
+//! Keeps secrets and personal data out of requests to hosted models: matches
+//! are swapped for placeholders such as `REDACTED_EMAIL_1` before a request is
+//! sent, and swapped back in the reply.
+
+use anyhow::{Result, anyhow};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Built-in detectors: placeholder label and pattern. When a pattern has a
+/// capture group, only the group is replaced.
+const DETECTORS: &[(&str, &str)] = &[
+    ("PRIVATE_KEY", r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----"),
+    ("JWT", r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+"),
+    ("AWS_KEY", r"\b(?:AKIA|ASIA|ABIA|ACCA)[0-9A-Z]{16}\b"),
+    ("AWS_SECRET", r#"(?i)aws_secret_access_key["']?\s*[=:]\s*["']?([A-Za-z0-9/+=]{40})"#),
+    ("EMAIL", r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b"),
+    ("IP", r"\b(?:\d{1,3}\.){3}\d{1,3}\b"),
+    ("IP", r"(?i)\b[0-9a-f]{1,4}(?::[0-9a-f]{0,4}){2,7}\b"),
+];
+
+/// Label of matches of the patterns in `redact.patterns`.
+const CUSTOM: &str = "SECRET";
+
+/// The `[redact]` section.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// Redact requests to hosted backends (OpenAI).
+    pub enabled: bool,
+    /// Redact requests to local backends (Ollama) as well.
+    pub local: bool,
+    /// Extra regular expressions to redact; with a capture group, only the group is replaced.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        Self { enabled: true, local: false, patterns: Vec::new() }
+    }
+}
+
+impl RedactConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        for pattern in &self.patterns {
+            Regex::new(pattern).map_err(|e| format!("redact.patterns: invalid pattern {:?}: {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    /// The redactor for a backend, or `None` when its requests go out as they are.
+    pub fn redactor(&self, hosted: bool) -> Result<Option<Redactor>> {
+        if !self.enabled || !(hosted || self.local) {
+            return Ok(None);
+        }
+        Redactor::new(&self.patterns).map(Some)
+    }
+}
+
+/// Finds sensitive values and replaces them with placeholders.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    detectors: Vec<(String, Regex)>,
+    placeholder: Regex,
+}
+
+/// The values replaced so far and their placeholders, used to restore them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redactions {
+    replaced: Vec<(String, String)>,
+}
+
+impl Redactor {
+    /// The built-in detectors plus `patterns`.
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let builtin = DETECTORS.iter().map(|(label, pattern)| (label.to_string(), pattern.to_string()));
+        let custom = patterns.iter().map(|pattern| (CUSTOM.to_string(), pattern.clone()));
+        let detectors = builtin
+            .chain(custom)
+            .map(|(label, pattern)| {
+                let regex = Regex::new(&pattern).map_err(|e| anyhow!("Invalid redaction pattern {:?}: {}", pattern, e))?;
+                Ok((label, regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { detectors, placeholder: placeholder_pattern() })
+    }
+
+    /// `text` with every match replaced, reusing the placeholders in
+    /// `redactions` for values seen before.
+    pub fn redact(&self, text: &str, redactions: &mut Redactions) -> String {
+        let mut text = text.to_string();
+        for (label, regex) in &self.detectors {
+            text = regex
+                .replace_all(&text, |captures: &Captures| {
+                    let whole = captures.get(0).map_or("", |m| m.as_str());
+                    let secret = captures.get(1).unwrap_or_else(|| captures.get(0).unwrap());
+                    if self.placeholder.is_match(secret.as_str()) || !worth_redacting(label, secret.as_str()) {
+                        return whole.to_string();
+                    }
+                    let start = secret.start() - captures.get(0).unwrap().start();
+                    let placeholder = redactions.placeholder(label, secret.as_str());
+                    format!("{}{}{}", &whole[..start], placeholder, &whole[start + secret.len()..])
+                })
+                .into_owned();
+        }
+        text
+    }
+}
+
+impl Redactions {
+    pub fn is_empty(&self) -> bool {
+        self.replaced.is_empty()
+    }
+
+    /// `text` with the placeholders swapped back for the original values.
+    pub fn restore(&self, text: &str) -> String {
+        if self.is_empty() {
+            return text.to_string();
+        }
+        placeholder_pattern()
+            .replace_all(text, |captures: &Captures| {
+                let placeholder = &captures[0];
+                self.replaced
+                    .iter()
+                    .find(|(_, p)| p == placeholder)
+                    .map_or_else(|| placeholder.to_string(), |(value, _)| value.clone())
+            })
+            .into_owned()
+    }
+
+    fn placeholder(&mut self, label: &str, value: &str) -> String {
+        if let Some((_, placeholder)) = self.replaced.iter().find(|(v, _)| v == value) {
+            return placeholder.clone();
+        }
+        let number = self.replaced.iter().filter(|(_, p)| p.starts_with(&format!("REDACTED_{}_", label))).count() + 1;
+        let placeholder = format!("REDACTED_{}_{}", label, number);
+        self.replaced.push((value.to_string(), placeholder.clone()));
+        placeholder
+    }
+}
+
+fn placeholder_pattern() -> Regex {
+    Regex::new(r"\bREDACTED_[A-Z_]+?_\d+\b").expect("valid placeholder pattern")
+}
+
+/// Loopback and unspecified addresses reveal nothing, and text that only
+/// looks like an address (times, MAC addresses) is left alone.
+fn worth_redacting(label: &str, value: &str) -> bool {
+    if label != "IP" {
+        return true;
+    }
+    let address: Option<IpAddr> = value
+        .parse::<Ipv4Addr>()
+        .map(IpAddr::V4)
+        .or_else(|_| value.parse::<Ipv6Addr>().map(IpAddr::V6))
+        .ok();
+    address.is_some_and(|address| !address.is_loopback() && !address.is_unspecified())
+}
@@ -0,0 +1,536 @@
+//! Dry runs of suggested commands: the command runs in a throwaway Linux
+//! namespace without network access, against a copy-on-write overlay of the
+//! current directory (or a full copy where no overlay can be mounted), and
+//! the changes it made are reported.
+
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Where no overlay can be mounted, the current directory is copied for each
+/// dry run, so it has to be small.
+pub const MAX_COPY_BYTES: u64 = 256 * 1024 * 1024;
+/// Dry runs still going after this long are stopped.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+/// Lines of output and changed paths shown before the rest is summarized.
+const SHOWN_LINES: usize = 20;
+
+/// Run by `unshare` inside the new namespaces: mounts an overlay over the
+/// current directory, or the copy in its place, makes every other mount
+/// read-only, gives the command an empty `/tmp` and `/dev/shm` and runs it.
+/// Arguments: current directory, the overlay's upper and work directories
+/// (empty for a copy), copy, command. Remounts keep the flags the kernel
+/// locks (nosuid, nodev, noexec, atime), and any that fails exits 125, so a
+/// command never runs with a writable mount left behind.
+const UNSHARE_SETUP: &str = r#"cwd=$1 upper=$2 work=$3 copy=$4; shift 4
+if [ -n "$upper" ]; then
+  mount -t overlay overlay -o "userxattr,lowerdir=$cwd,upperdir=$upper,workdir=$work" "$cwd" || exit 125
+else
+  mount --bind "$copy" "$cwd" || exit 125
+fi
+mounts=$(cat /proc/self/mounts)
+printf '%s\n' "$mounts" | while read -r _ target _ options _; do
+  target=$(printf '%b' "$target")
+  case "$target" in /proc|/proc/*|"$cwd") continue ;; esac
+  flags=remount,bind,ro atime=strictatime
+  for option in $(printf '%s' "$options" | tr , ' '); do
+    case "$option" in
+      nosuid|nodev|noexec|nodiratime) flags=$flags,$option ;;
+      noatime|relatime) atime=$option ;;
+    esac
+  done
+  mount -o "$flags,$atime" "$target" || exit 125
+done || exit 125
+if [ -d /dev/shm ]; then mount -t tmpfs tmpfs /dev/shm || exit 125; fi
+case "$cwd" in /tmp|/tmp/*) ;; *) mount -t tmpfs tmpfs /tmp || exit 125 ;; esac
+cd "$cwd" || exit 125
+exec "$@""#;
+
+/// How a dry run is isolated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// bubblewrap (`bwrap`).
+    Bubblewrap,
+    /// `unshare` from util-linux, with an unprivileged user namespace.
+    Unshare,
+}
+
+impl Isolation {
+    /// The available tool, preferring bubblewrap.
+    pub fn detect() -> Option<Self> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        if find_program("bwrap").is_some() {
+            Some(Isolation::Bubblewrap)
+        } else if find_program("unshare").is_some() {
+            Some(Isolation::Unshare)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Isolation::Bubblewrap => "bubblewrap",
+            Isolation::Unshare => "unshare",
+        }
+    }
+
+    fn command(self, view: &View, cwd: &Path, args: &[String]) -> Command {
+        let mut command = match self {
+            Isolation::Bubblewrap => {
+                let mut command = Command::new("bwrap");
+                command.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]);
+                match view {
+                    View::Overlay { upper, work } => {
+                        command.arg("--overlay-src").arg(cwd).arg("--overlay").args([*upper, *work, cwd]);
+                    }
+                    View::Copy(copy) => {
+                        command.arg("--bind").args([*copy, cwd]);
+                    }
+                }
+                command.arg("--chdir").arg(cwd).args(["--unshare-all", "--die-with-parent", "--"]);
+                command
+            }
+            Isolation::Unshare => {
+                let mut command = Command::new("unshare");
+                command
+                    .args(["--user", "--map-root-user", "--mount", "--net", "--pid", "--fork", "--kill-child"])
+                    .args(["sh", "-c", UNSHARE_SETUP, "sh"])
+                    .arg(cwd);
+                match view {
+                    View::Overlay { upper, work } => command.args([*upper, *work, Path::new("")]),
+                    View::Copy(copy) => command.args([Path::new(""), Path::new(""), *copy]),
+                };
+                command
+            }
+        };
+        command.args(args);
+        command
+    }
+}
+
+/// What the command sees in place of the current directory.
+enum View<'a> {
+    /// An overlay on it, recording the changes in `upper`.
+    Overlay { upper: &'a Path, work: &'a Path },
+    /// A copy of it.
+    Copy(&'a Path),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A path below the current directory that the command changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+}
+
+/// What a command did when run in the sandbox.
+#[derive(Debug, Clone)]
+pub struct DryRun {
+    pub isolation: Isolation,
+    /// Whether the command ran on an overlay rather than a copy.
+    pub overlay: bool,
+    pub cwd: PathBuf,
+    /// Exit code, or `None` when the command was killed.
+    pub status: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub changes: Vec<Change>,
+}
+
+/// Runs `args` in a sandbox against an overlay on `cwd` or, where none can
+/// be mounted, a copy of it.
+pub fn dry_run(args: &[String], cwd: &Path) -> Result<DryRun> {
+    let isolation = Isolation::detect()
+        .ok_or_else(|| anyhow!("--sandbox needs bubblewrap (bwrap) or unshare on Linux, and neither was found"))?;
+    let scratch = Scratch::new()?;
+
+    let (upper, work) = (scratch.path().join("upper"), scratch.path().join("work"));
+    // Overlay options can't hold these characters, and the layers must not overlap.
+    let overlay_possible = ![cwd, scratch.path()].iter().any(|path| path.to_string_lossy().contains([',', ':', '\\']))
+        && !scratch.path().starts_with(cwd)
+        && !cwd.starts_with(scratch.path());
+    if overlay_possible {
+        std::fs::create_dir(&upper)?;
+        std::fs::create_dir(&work)?;
+        // Without overlay support the sandbox doesn't start, and a copy is used instead.
+        if let Ok(execution) = run(isolation, &View::Overlay { upper: &upper, work: &work }, cwd, args, TIMEOUT) {
+            return Ok(DryRun::new(isolation, true, cwd, execution, overlay_changes(cwd, &upper)?));
+        }
+    }
+
+    let copy = scratch.path().join("copy");
+    std::fs::create_dir(&copy)?;
+    let mut budget = MAX_COPY_BYTES;
+    copy_tree(cwd, &copy, scratch.path(), &mut budget)?;
+    let execution = run(isolation, &View::Copy(&copy), cwd, args, TIMEOUT)?;
+    let changes = changes(cwd, &copy, Some(scratch.path()))?;
+    Ok(DryRun::new(isolation, false, cwd, execution, changes))
+}
+
+/// How a command run in the sandbox ended, and what it printed.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    /// Exit code, or `None` when the command was killed.
+    pub status: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs `args` in a sandbox with `dir` as the current directory and the only
+/// place it can change, stopping it after `timeout`.
+pub fn execute(args: &[String], dir: &Path, timeout: Duration) -> Result<Execution> {
+    let isolation = Isolation::detect()
+        .ok_or_else(|| anyhow!("Running commands in a sandbox needs bubblewrap (bwrap) or unshare on Linux, and neither was found"))?;
+    run(isolation, &View::Copy(dir), dir, args, timeout)
+}
+
+/// Runs `args` isolated, with `view` in place of `cwd`, for at most `timeout`.
+fn run(isolation: Isolation, view: &View, cwd: &Path, args: &[String], timeout: Duration) -> Result<Execution> {
+    let mut child = isolation
+        .command(view, cwd, args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start {}: {}", isolation.name(), e))?;
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let started = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            timed_out = true;
+            child.kill().ok();
+            break child.wait()?;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    // The tool's own complaints mean the command never ran.
+    let setup_failed = status.code() == Some(125)
+        || (!status.success() && (stderr.starts_with("bwrap:") || stderr.starts_with("unshare:")));
+    if setup_failed && !timed_out {
+        return Err(anyhow!("Could not start the sandbox with {}: {}", isolation.name(), stderr.trim()));
+    }
+
+    Ok(Execution { status: status.code(), timed_out, stdout, stderr })
+}
+
+impl DryRun {
+    fn new(isolation: Isolation, overlay: bool, cwd: &Path, execution: Execution, changes: Vec<Change>) -> Self {
+        let Execution { status, timed_out, stdout, stderr } = execution;
+        Self { isolation, overlay, cwd: cwd.to_path_buf(), status, timed_out, stdout, stderr, changes }
+    }
+
+    pub fn render(&self) -> String {
+        let status = match self.status {
+            _ if self.timed_out => format!("stopped after {}s", TIMEOUT.as_secs()),
+            Some(code) => format!("exit status {}", code),
+            None => String::from("killed by a signal"),
+        };
+        let view = if self.overlay { "overlay" } else { "full copy" };
+        let mut out = vec![format!("Sandbox ({}, {}, no network): {}", self.isolation.name(), view, status)];
+        for (title, text) in [("Output", &self.stdout), ("Errors", &self.stderr)] {
+            let lines: Vec<&str> = text.lines().collect();
+            if lines.is_empty() {
+                continue;
+            }
+            out.push(format!("{}:", title));
+            out.extend(lines.iter().take(SHOWN_LINES).map(|line| format!("  {}", line)));
+            if lines.len() > SHOWN_LINES {
+                out.push(format!("  ... {} more lines", lines.len() - SHOWN_LINES));
+            }
+        }
+        if self.changes.is_empty() {
+            out.push(format!("No files in {} were changed.", self.cwd.display()));
+        } else {
+            out.push(format!("Changes to {} (discarded):", self.cwd.display()));
+            for change in self.changes.iter().take(SHOWN_LINES) {
+                let kind = match change.kind {
+                    ChangeKind::Created => "created",
+                    ChangeKind::Modified => "modified",
+                    ChangeKind::Deleted => "deleted",
+                };
+                out.push(format!("  {:<9} {}", kind, change.path.display()));
+            }
+            if self.changes.len() > SHOWN_LINES {
+                out.push(format!("  ... {} more", self.changes.len() - SHOWN_LINES));
+            }
+        }
+        out.join("\n")
+    }
+}
+
+/// The paths below `before` that were created, modified or deleted in
+/// `after`, ordered by path.
+pub fn diff(before: &Path, after: &Path) -> Result<Vec<Change>> {
+    changes(before, after, None)
+}
+
+/// [`diff`], leaving out `excluded` wherever it turns up, e.g. the copy
+/// itself when the temporary directory lies below the current one.
+pub(crate) fn changes(before: &Path, after: &Path, excluded: Option<&Path>) -> Result<Vec<Change>> {
+    let old = entries(before, excluded)?;
+    let new = entries(after, excluded)?;
+    let mut changes = Vec::new();
+    for (path, entry) in &old {
+        match new.get(path) {
+            None => changes.push(Change { kind: ChangeKind::Deleted, path: path.clone() }),
+            Some(other) if !same(entry, other, &before.join(path), &after.join(path))? => {
+                changes.push(Change { kind: ChangeKind::Modified, path: path.clone() })
+            }
+            Some(_) => {}
+        }
+    }
+    for path in new.keys().filter(|path| !old.contains_key(*path)) {
+        changes.push(Change { kind: ChangeKind::Created, path: path.clone() });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+#[derive(Debug, PartialEq)]
+enum Entry {
+    Directory,
+    File { len: u64, mode: u32 },
+    Symlink(PathBuf),
+}
+
+fn same(a: &Entry, b: &Entry, a_path: &Path, b_path: &Path) -> Result<bool> {
+    Ok(match (a, b) {
+        (Entry::File { .. }, Entry::File { .. }) => a == b && std::fs::read(a_path)? == std::fs::read(b_path)?,
+        _ => a == b,
+    })
+}
+
+/// Everything below `root`, keyed by path relative to it.
+fn entries(root: &Path, excluded: Option<&Path>) -> Result<BTreeMap<PathBuf, Entry>> {
+    let mut entries = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for item in std::fs::read_dir(&dir).map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))? {
+            let path = item?.path();
+            if Some(path.as_path()) == excluded {
+                continue;
+            }
+            let metadata = std::fs::symlink_metadata(&path)?;
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            entries.insert(relative, entry(&path, &metadata)?);
+            if metadata.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn entry(path: &Path, metadata: &std::fs::Metadata) -> Result<Entry> {
+    Ok(if metadata.file_type().is_symlink() {
+        Entry::Symlink(std::fs::read_link(path)?)
+    } else if metadata.is_dir() {
+        Entry::Directory
+    } else {
+        Entry::File { len: metadata.len(), mode: mode(metadata) }
+    })
+}
+
+/// The changes an overlay recorded in `upper` to the directory `lower`, as
+/// [`diff`] would list them: whiteouts are deletions, and an opaque
+/// directory replaces the one below it.
+fn overlay_changes(lower: &Path, upper: &Path) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    upper_changes(lower, upper, Path::new(""), &mut changes)?;
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+fn upper_changes(lower: &Path, upper: &Path, dir: &Path, found: &mut Vec<Change>) -> Result<()> {
+    let below = |root: &Path, path: &Path, kind: ChangeKind, found: &mut Vec<Change>| -> Result<()> {
+        for relative in entries(&root.join(path), None)?.into_keys() {
+            found.push(Change { kind, path: path.join(relative) });
+        }
+        Ok(())
+    };
+    for item in std::fs::read_dir(upper.join(dir)).map_err(|e| anyhow!("Failed to read {}: {}", upper.display(), e))? {
+        let path = dir.join(item?.file_name());
+        let new = std::fs::symlink_metadata(upper.join(&path))?;
+        let old = std::fs::symlink_metadata(lower.join(&path)).ok();
+        match old {
+            None if is_whiteout(&new) => {}
+            None => {
+                found.push(Change { kind: ChangeKind::Created, path: path.clone() });
+                if new.is_dir() {
+                    below(upper, &path, ChangeKind::Created, found)?;
+                }
+            }
+            Some(old) if is_whiteout(&new) => {
+                found.push(Change { kind: ChangeKind::Deleted, path: path.clone() });
+                if old.is_dir() {
+                    below(lower, &path, ChangeKind::Deleted, found)?;
+                }
+            }
+            Some(old) if old.is_dir() && new.is_dir() => {
+                if is_opaque(&upper.join(&path)) {
+                    let replaced = changes(&lower.join(&path), &upper.join(&path), None)?;
+                    found.extend(replaced.into_iter().map(|change| Change { path: path.join(change.path), ..change }));
+                } else {
+                    upper_changes(lower, upper, &path, found)?;
+                }
+            }
+            Some(old) => {
+                let (old_entry, new_entry) = (entry(&lower.join(&path), &old)?, entry(&upper.join(&path), &new)?);
+                if !same(&old_entry, &new_entry, &lower.join(&path), &upper.join(&path))? {
+                    found.push(Change { kind: ChangeKind::Modified, path: path.clone() });
+                }
+                if old.is_dir() {
+                    below(lower, &path, ChangeKind::Deleted, found)?;
+                } else if new.is_dir() {
+                    below(upper, &path, ChangeKind::Created, found)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether an overlay's upper directory marks the path below as deleted.
+#[cfg(unix)]
+fn is_whiteout(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+#[cfg(not(unix))]
+fn is_whiteout(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Whether an overlay's upper directory hides the directory below it.
+#[cfg(target_os = "linux")]
+fn is_opaque(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    [c"user.overlay.opaque", c"trusted.overlay.opaque"].iter().any(|name| {
+        let mut value = [0u8; 1];
+        // SAFETY: both names are NUL-terminated and `value` is as long as the size passed.
+        let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        len == 1 && value[0] == b'y'
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_opaque(_dir: &Path) -> bool {
+    false
+}
+
+/// Copies the files, directories and symlinks below `from` into `to`,
+/// except `excluded`, failing once more than `budget` bytes would be copied.
+fn copy_tree(from: &Path, to: &Path, excluded: &Path, budget: &mut u64) -> Result<()> {
+    for item in std::fs::read_dir(from).map_err(|e| anyhow!("Failed to read {}: {}", from.display(), e))? {
+        let item = item?;
+        let source = item.path();
+        if source == excluded {
+            continue;
+        }
+        let target = to.join(item.file_name());
+        let metadata = std::fs::symlink_metadata(&source)?;
+        if metadata.file_type().is_symlink() {
+            symlink(&std::fs::read_link(&source)?, &target)?;
+        } else if metadata.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_tree(&source, &target, excluded, budget)?;
+        } else if metadata.is_file() {
+            *budget = budget.checked_sub(metadata.len()).ok_or_else(|| {
+                anyhow!(
+                    "The current directory holds more than {} MB, too much to copy for --sandbox",
+                    MAX_COPY_BYTES / 1024 / 1024
+                )
+            })?;
+            std::fs::copy(&source, &target).map_err(|e| anyhow!("Failed to copy {}: {}", source.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// A temporary directory, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new() -> Result<Self> {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        let path = std::env::temp_dir().join(format!("toli-sandbox-{}-{}", std::process::id(), nanos));
+        std::fs::create_dir(&path).map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+        // Resolved like the current directory, so the copy can recognize it.
+        Ok(Self(path.canonicalize().unwrap_or(path)))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut bytes).ok();
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+    u32::from(metadata.permissions().readonly())
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! Explaining whole scripts: long scripts are split into sections that are
+//! explained one at a time, then summarized, with toli's own list of the
+//! lines that look risky.
+
+use anyhow::Result;
+use std::io::Write;
+use crate::classify::{self, Category, Risk};
+use crate::llm::LLMBackend;
+use crate::shell::{self, Command};
+
+/// Sections are kept to at most this many lines so each explanation stays focused.
+pub const MAX_SECTION_LINES: usize = 40;
+
+/// A run of lines of the script, numbered from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub first_line: usize,
+    pub last_line: usize,
+    pub text: String,
+}
+
+/// A command that `classify::assess` rates as medium or high risk, with the
+/// line it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskyLine {
+    pub line: usize,
+    pub command: String,
+    pub risk: Risk,
+    pub category: Category,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptExplanation {
+    pub lines: usize,
+    /// Each section with the model's explanation of it.
+    pub sections: Vec<(Section, String)>,
+    /// Overview of the whole script; empty when it fits in one section.
+    pub summary: String,
+    pub risks: Vec<RiskyLine>,
+}
+
+/// Splits `script` into sections of at most `max_lines` lines, breaking at
+/// blank lines where possible and never inside a here-document.
+pub fn sections(script: &str, max_lines: usize) -> Vec<Section> {
+    let lines: Vec<&str> = script.lines().collect();
+    let in_heredoc = heredoc_lines(script, lines.len());
+
+    // Blocks of lines separated by blank lines, as [start, end) indexes.
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let mut start = None;
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() && !in_heredoc[i] {
+            if let Some(start) = start.take() {
+                blocks.push((start, i));
+            }
+        } else {
+            start.get_or_insert(i);
+        }
+    }
+    if let Some(start) = start {
+        blocks.push((start, lines.len()));
+    }
+
+    // Merge neighbouring blocks up to the limit; split blocks that are longer,
+    // letting a section run on to the end of a here-document.
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in blocks {
+        match ranges.last_mut() {
+            Some((first, last)) if end - *first <= max_lines => *last = end,
+            _ => {
+                let mut chunk_start = start;
+                while end - chunk_start > max_lines {
+                    let mut chunk_end = chunk_start + max_lines;
+                    while chunk_end < end && in_heredoc[chunk_end] {
+                        chunk_end += 1;
+                    }
+                    ranges.push((chunk_start, chunk_end));
+                    chunk_start = chunk_end;
+                }
+                if chunk_start < end {
+                    ranges.push((chunk_start, end));
+                }
+            }
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| Section {
+            first_line: start + 1,
+            last_line: end,
+            text: lines[start..end].join("\n"),
+        })
+        .collect()
+}
+
+/// The commands of the script that are medium or high risk, leaving out
+/// comments and here-documents. A pipeline of simple commands is assessed
+/// as a whole, across line continuations, so `curl ... | sh` is caught.
+pub fn risky_lines(script: &str) -> Vec<RiskyLine> {
+    let Ok(list) = shell::parse(script) else {
+        // Make what we can of a script that doesn't parse, line by line.
+        return script
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .filter_map(|(i, line)| risky(line, i + 1))
+            .collect();
+    };
+    let mut spans = Vec::new();
+    for pipeline in list.statements() {
+        let simple: Vec<_> = pipeline
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::Simple(simple) => Some(simple),
+                _ => None,
+            })
+            .collect();
+        match (simple.first(), simple.last()) {
+            (Some(first), Some(last)) if simple.len() == pipeline.commands.len() => spans.push(first.span.start..last.span.end),
+            // The commands inside compound ones are statements of their own.
+            _ => {
+                spans.extend(simple.iter().map(|command| command.span.clone()));
+                for command in pipeline.commands.iter().filter(|command| !matches!(command, Command::Simple(_))) {
+                    spans.extend(command.redirects().iter().map(|redirect| redirect.span.clone()));
+                }
+            }
+        }
+    }
+    spans.sort_by_key(|span| span.start);
+    spans.into_iter().filter_map(|span| risky(&script[span.clone()], line_of(script, span.start))).collect()
+}
+
+fn risky(command: &str, line: usize) -> Option<RiskyLine> {
+    let (risk, category) = classify::assess(command);
+    let command = command
+        .lines()
+        .map(|line| line.trim().trim_end_matches('\\').trim_end())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (risk > Risk::Low).then_some(RiskyLine { line, command, risk, category })
+}
+
+/// Explains `script` section by section and, when there is more than one
+/// section, asks for a summary of the whole.
+pub async fn explain(backend: &dyn LLMBackend, script: &str, additional_context: &str) -> Result<ScriptExplanation> {
+    let sections = sections(script, MAX_SECTION_LINES);
+    let total = sections.len();
+    let mut explained = Vec::new();
+    for (i, section) in sections.into_iter().enumerate() {
+        if total > 1 {
+            eprint!("\rExplaining section {}/{}...", i + 1, total);
+            std::io::stderr().flush().ok();
+        }
+        let explanation = backend.explain_command(&section.text, additional_context).await?;
+        explained.push((section, explanation.option().explanation.trim().to_string()));
+    }
+
+    let summary = if total > 1 {
+        eprintln!();
+        let outline = explained
+            .iter()
+            .map(|(section, explanation)| format!("Lines {}-{}: {}", section.first_line, section.last_line, explanation))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        backend.summarize_script(&outline, additional_context).await?.trim().to_string()
+    } else {
+        String::new()
+    };
+
+    Ok(ScriptExplanation {
+        lines: script.lines().count(),
+        sections: explained,
+        summary,
+        risks: risky_lines(script),
+    })
+}
+
+impl ScriptExplanation {
+    /// The explanation as printed by `toli --explain --file <name>`.
+    pub fn render(&self, name: &str) -> String {
+        let mut out = vec![format!(
+            "{} ({} lines, {} section{})",
+            name,
+            self.lines,
+            self.sections.len(),
+            if self.sections.len() == 1 { "" } else { "s" }
+        )];
+        for (section, explanation) in &self.sections {
+            out.push(String::new());
+            out.push(format!("Lines {}-{}", section.first_line, section.last_line));
+            out.push(explanation.clone());
+        }
+        if !self.summary.is_empty() {
+            out.push(String::new());
+            out.push(String::from("Summary"));
+            out.push(self.summary.clone());
+        }
+        out.push(String::new());
+        out.push(String::from("Risks"));
+        if self.risks.is_empty() {
+            out.push(String::from("  No risky commands found"));
+        }
+        for risky in &self.risks {
+            out.push(format!("  line {}: {} ({} risk, {})", risky.line, risky.command, risky.risk, risky.category));
+        }
+        out.join("\n")
+    }
+}
+
+/// Marks the lines that are the body or delimiter of a here-document, as
+/// far as the script parses.
+fn heredoc_lines(script: &str, lines: usize) -> Vec<bool> {
+    let mut marks = vec![false; lines];
+    let Ok(list) = shell::parse(script) else {
+        return marks;
+    };
+    for heredoc in list.here_docs().into_iter().filter(|heredoc| !heredoc.span.is_empty()) {
+        let first = line_of(script, heredoc.span.start);
+        let last = line_of(script, heredoc.span.end - 1).min(lines);
+        for mark in marks.iter_mut().take(last).skip(first - 1) {
+            *mark = true;
+        }
+    }
+    marks
+}
+
+/// The line, numbered from 1, that byte `offset` of `text` is on.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+/// A credential that is never printed: `Debug` and `Display` show a
+/// placeholder, so it cannot leak through `{:?}` or error messages.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Returns the actual value. Only call this where the key is sent to the API.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([redacted])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Runs `command` through the shell (e.g. `pass show openai`) and returns the
+/// first line of its output. The output is never included in errors.
+pub fn from_command(command: &str) -> Result<Secret> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| anyhow!("Failed to run api_key_cmd '{}': {}", command, e))?;
+    if !output.status.success() {
+        return Err(anyhow!("api_key_cmd '{}' exited with status: {}", command, output.status));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| anyhow!("api_key_cmd '{}' did not print valid UTF-8", command))?;
+    let key = stdout.lines().next().unwrap_or("").trim();
+    if key.is_empty() {
+        return Err(anyhow!("api_key_cmd '{}' printed nothing", command));
+    }
+    Ok(Secret::new(key))
+}
+
+pub fn from_env(name: &str) -> Result<Secret> {
+    match std::env::var(name) {
+        Ok(value) if !value.trim().is_empty() => Ok(Secret::new(value.trim())),
+        _ => Err(anyhow!("Environment variable ${} (api_key_env) is not set", name)),
+    }
+}
+
+/// Reads a key from the first line of `path`, warning when the file is
+/// readable by other users.
+pub fn from_file(path: &Path) -> Result<Secret> {
+    warn_if_accessible_by_others(path, "API key file");
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read api_key_file {}: {}", path.display(), e))?;
+    let key = contents.lines().next().unwrap_or("").trim();
+    if key.is_empty() {
+        return Err(anyhow!("api_key_file {} is empty", path.display()));
+    }
+    Ok(Secret::new(key))
+}
+
+/// Prints a warning when `path` grants any permissions beyond 0600.
+#[cfg(unix)]
+pub fn warn_if_accessible_by_others(path: &Path, what: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            eprintln!(
+                "Warning: {} {} has permissions {:o}; run 'chmod 600 {}' so other users cannot read it",
+                what, path.display(), mode, path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn warn_if_accessible_by_others(_path: &Path, _what: &str) {}
+
+/// Writes `contents` to `path`, creating the file readable only by its owner.
+pub fn write_private(path: &Path, contents: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        // `mode` only applies to newly created files; tighten existing ones too.
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1278 @@
+//! A parser for POSIX shell command lines and scripts, with the bash
+//! extensions models commonly use, producing a syntax tree for toli's
+//! command analysis.
+//!
+//! Words are not expanded: `$HOME` stays a parameter and `*.txt` a literal.
+
+use std::fmt;
+use std::ops::Range;
+
+/// Words that end a compound command and can't start a command.
+const CLOSING_WORDS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Redirection operators, longest first so that `>>` isn't read as `>`.
+const REDIRECT_OPERATORS: &[(&str, RedirectOp)] = &[
+    ("&>>", RedirectOp::AppendOutputAndError),
+    ("&>", RedirectOp::OutputAndError),
+    ("<<<", RedirectOp::HereString),
+    ("<<-", RedirectOp::HereDoc),
+    ("<<", RedirectOp::HereDoc),
+    ("<&", RedirectOp::DupInput),
+    ("<>", RedirectOp::ReadWrite),
+    (">>", RedirectOp::Append),
+    (">&", RedirectOp::DupOutput),
+    (">|", RedirectOp::Clobber),
+    ("<", RedirectOp::Input),
+    (">", RedirectOp::Output),
+];
+
+/// Programs that run the command given in their arguments, e.g. `sudo -u www
+/// rm file` or `find | xargs rm`.
+pub const WRAPPERS: &[&str] = &["sudo", "doas", "env", "exec", "nice", "nohup", "time", "timeout", "xargs"];
+
+/// Commands separated by `;`, `&` or newlines; a whole script is one list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+/// Pipelines joined by `&&` and `||`, e.g. `make && make install || echo failed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Run in the background with `&`.
+    pub background: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+/// Commands joined by `|` (or bash's `|&`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    /// Prefixed with `!`.
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// `name() { ...; }` or `function name { ...; }`.
+    Function { name: String, body: Box<Command> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    /// `{ list; }`
+    Group(List),
+    /// `( list )`
+    Subshell(List),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If { branches: Vec<(List, List)>, otherwise: Option<List> },
+    /// `while list; do list; done`, or `until` when `until` is set.
+    While { until: bool, condition: List, body: List },
+    /// `for name [in words]; do list; done`, or bash's `select` when `select`
+    /// is set; `words` is `None` without `in`.
+    For { select: bool, variable: String, words: Option<Vec<Word>>, body: List },
+    /// bash `for ((init; test; step)); do list; done`, with the text between the parentheses.
+    ArithmeticFor { header: String, body: List },
+    /// `case word in pattern) list;; ... esac`
+    Case { word: Word, arms: Vec<CaseArm> },
+    /// bash `[[ expression ]]`, as the words and operators inside.
+    Conditional(Vec<Word>),
+    /// bash `(( expression ))`, with the text between the parentheses.
+    Arithmetic(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// Assignments, words and redirections, e.g. `LANG=C sort -u < in > out`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    /// The program and its arguments.
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    pub span: Range<usize>,
+}
+
+/// `name=value`, `name+=value` or bash's `name=(a b c)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Value,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Scalar(Word),
+    Array(Vec<Word>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// The file descriptor written before the operator, as in `2>`.
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    pub target: RedirectTarget,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+    /// `>|`
+    Clobber,
+    /// `<>`
+    ReadWrite,
+    /// `<&`
+    DupInput,
+    /// `>&`
+    DupOutput,
+    /// `&>`
+    OutputAndError,
+    /// `&>>`
+    AppendOutputAndError,
+    /// `<<` or `<<-`
+    HereDoc,
+    /// `<<<`
+    HereString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectTarget {
+    Word(Word),
+    HereDoc(HereDoc),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereDoc {
+    pub delimiter: String,
+    /// The delimiter was quoted, so the body is not expanded.
+    pub quoted: bool,
+    /// `<<-`, which strips leading tabs from the body.
+    pub strip_tabs: bool,
+    /// The body, one `\n`-terminated line per line.
+    pub body: String,
+    /// Byte range of the body and the delimiter line in the parsed text.
+    pub span: Range<usize>,
+}
+
+/// A word as written, split into its quoted and unquoted parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    /// Byte range of the word in the parsed text.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Unquoted text, with backslash escapes removed.
+    Literal(String),
+    /// `'text'` or bash's `$'text'`, without the quotes.
+    SingleQuoted(String),
+    /// `"..."`, which can contain expansions.
+    DoubleQuoted(Vec<WordPart>),
+    /// `$name`, `$1`, `$?` or `${...}`, as written.
+    Parameter(String),
+    /// `$(list)` or `` `list` ``, with the text as written. Spans inside
+    /// backquotes are relative to the text between them.
+    CommandSubstitution { text: String, body: List },
+    /// `$((expression))`, with the text between the parentheses.
+    Arithmetic(String),
+    /// `<(list)` or, when `output` is set, `>(list)`.
+    ProcessSubstitution { output: bool, text: String, body: List },
+}
+
+/// Why a command line could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// Byte offset of the error in the parsed text.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {}, column {})", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a command line or script.
+pub fn parse(source: &str) -> Result<List, ParseError> {
+    let mut parser = Parser { src: source, pos: 0, heredoc_resume: None };
+    let list = parser.parse_list(&[])?;
+    parser.skip_linebreaks();
+    if parser.pos < source.len() {
+        return Err(parser.unexpected());
+    }
+    Ok(list)
+}
+
+impl List {
+    /// Every pipeline in the list, including those inside compound commands,
+    /// functions and substitutions; outer pipelines come before inner ones.
+    pub fn pipelines(&self) -> Vec<&Pipeline> {
+        let mut pipelines = Vec::new();
+        collect_pipelines(self, &mut pipelines);
+        pipelines
+    }
+
+    /// The pipelines of the list and of the compound commands and functions
+    /// in it, leaving out those inside substitutions: the statements a
+    /// script is made of, outer ones before inner ones.
+    pub fn statements(&self) -> Vec<&Pipeline> {
+        let mut pipelines = Vec::new();
+        collect_statements(self, &mut pipelines);
+        pipelines
+    }
+
+    /// The here-documents of the list's statements.
+    pub fn here_docs(&self) -> Vec<&HereDoc> {
+        self.statements()
+            .into_iter()
+            .flat_map(|pipeline| &pipeline.commands)
+            .flat_map(Command::redirects)
+            .filter_map(|redirect| match &redirect.target {
+                RedirectTarget::HereDoc(heredoc) => Some(heredoc),
+                RedirectTarget::Word(_) => None,
+            })
+            .collect()
+    }
+
+    /// Every simple command in the list, nested ones included.
+    pub fn simple_commands(&self) -> Vec<&SimpleCommand> {
+        self.pipelines()
+            .into_iter()
+            .flat_map(|pipeline| &pipeline.commands)
+            .filter_map(|command| match command {
+                Command::Simple(simple) => Some(simple),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl AndOr {
+    pub fn pipelines(&self) -> impl Iterator<Item = &Pipeline> {
+        std::iter::once(&self.first).chain(self.rest.iter().map(|(_, pipeline)| pipeline))
+    }
+}
+
+impl Command {
+    /// Redirections applying to the whole command.
+    pub fn redirects(&self) -> &[Redirect] {
+        match self {
+            Command::Simple(simple) => &simple.redirects,
+            Command::Compound(_, redirects) => redirects,
+            Command::Function { .. } => &[],
+        }
+    }
+}
+
+impl SimpleCommand {
+    /// The program name as written, e.g. `/usr/bin/env`.
+    pub fn program(&self) -> Option<String> {
+        self.words.first().map(Word::value)
+    }
+}
+
+impl Word {
+    /// The word with quotes removed and expansions left as written, e.g.
+    /// `"$HOME"/'my dir'` gives `$HOME/my dir`.
+    pub fn value(&self) -> String {
+        let mut value = String::new();
+        push_value(&self.parts, &mut value);
+        value
+    }
+
+    /// The value, if the word contains no expansions.
+    pub fn literal(&self) -> Option<String> {
+        fn is_literal(parts: &[WordPart]) -> bool {
+            parts.iter().all(|part| match part {
+                WordPart::Literal(_) | WordPart::SingleQuoted(_) => true,
+                WordPart::DoubleQuoted(parts) => is_literal(parts),
+                _ => false,
+            })
+        }
+        is_literal(&self.parts).then(|| self.value())
+    }
+
+    /// Whether any part of the word is quoted or escaped in `source`.
+    pub fn is_quoted(&self, source: &str) -> bool {
+        source.get(self.span.clone()).is_some_and(|text| text.contains(['\'', '"', '\\']))
+    }
+
+    /// The bodies of the command and process substitutions in the word.
+    pub fn substitutions(&self) -> Vec<&List> {
+        fn collect<'a>(parts: &'a [WordPart], lists: &mut Vec<&'a List>) {
+            for part in parts {
+                match part {
+                    WordPart::DoubleQuoted(parts) => collect(parts, lists),
+                    WordPart::CommandSubstitution { body, .. } | WordPart::ProcessSubstitution { body, .. } => {
+                        lists.push(body)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut lists = Vec::new();
+        collect(&self.parts, &mut lists);
+        lists
+    }
+}
+
+/// The index in `words` of the program that actually runs, after any
+/// [`WRAPPERS`] with their flags and operands, e.g. 3 for `sudo -u www rm`.
+/// Equals `words.len()` when a wrapper runs nothing, as in `env`.
+pub fn wrapped_program(words: &[&str]) -> usize {
+    let mut i = 0;
+    while let Some(&word) = words.get(i) {
+        let wrapper = word.rsplit('/').next().unwrap_or(word);
+        if !WRAPPERS.contains(&wrapper) {
+            break;
+        }
+        i += 1;
+        let valued = wrapper_options(wrapper);
+        while let Some(&arg) = words.get(i) {
+            if arg == "--" {
+                i += 1;
+                break;
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                i += if valued.contains(&arg) { 2 } else { 1 };
+            } else if wrapper == "env" && arg.contains('=') {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        if wrapper == "timeout" {
+            // The duration.
+            i += 1;
+        }
+    }
+    i.min(words.len())
+}
+
+/// The options of a wrapper program that take a separate value.
+fn wrapper_options(wrapper: &str) -> &'static [&'static str] {
+    match wrapper {
+        "sudo" => &["-u", "-g", "-p", "-C", "-D", "-h", "-r", "-t", "-U", "-T", "--user", "--group", "--prompt", "--chdir"],
+        "doas" => &["-u", "-C"],
+        "env" => &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"],
+        "exec" => &["-a"],
+        "nice" => &["-n", "--adjustment"],
+        "time" => &["-f", "-o", "--format", "--output"],
+        "timeout" => &["-s", "-k", "--signal", "--kill-after"],
+        "xargs" => &["-a", "-d", "-E", "-I", "-L", "-n", "-P", "-s", "--arg-file", "--delimiter", "--max-args", "--max-procs"],
+        _ => &[],
+    }
+}
+
+fn push_value(parts: &[WordPart], value: &mut String) {
+    for part in parts {
+        match part {
+            WordPart::Literal(text) | WordPart::SingleQuoted(text) | WordPart::Parameter(text) => value.push_str(text),
+            WordPart::DoubleQuoted(parts) => push_value(parts, value),
+            WordPart::CommandSubstitution { text, .. } | WordPart::ProcessSubstitution { text, .. } => {
+                value.push_str(text)
+            }
+            WordPart::Arithmetic(expression) => value.push_str(&format!("$(({}))", expression)),
+        }
+    }
+}
+
+fn collect_pipelines<'a>(list: &'a List, pipelines: &mut Vec<&'a Pipeline>) {
+    for pipeline in list.items.iter().flat_map(AndOr::pipelines) {
+        pipelines.push(pipeline);
+        for command in &pipeline.commands {
+            for nested in nested_lists(command) {
+                collect_pipelines(nested, pipelines);
+            }
+        }
+    }
+}
+
+fn collect_statements<'a>(list: &'a List, pipelines: &mut Vec<&'a Pipeline>) {
+    for pipeline in list.items.iter().flat_map(AndOr::pipelines) {
+        pipelines.push(pipeline);
+        for command in &pipeline.commands {
+            for body in bodies(command) {
+                collect_statements(body, pipelines);
+            }
+        }
+    }
+}
+
+/// The bodies and conditions of a compound command or function.
+fn bodies(command: &Command) -> Vec<&List> {
+    match command {
+        Command::Simple(_) => Vec::new(),
+        Command::Compound(compound, _) => match compound {
+            CompoundCommand::Group(body) | CompoundCommand::Subshell(body) => vec![body],
+            CompoundCommand::If { branches, otherwise } => {
+                branches.iter().flat_map(|(condition, body)| [condition, body]).chain(otherwise).collect()
+            }
+            CompoundCommand::While { condition, body, .. } => vec![condition, body],
+            CompoundCommand::For { body, .. } | CompoundCommand::ArithmeticFor { body, .. } => vec![body],
+            CompoundCommand::Case { arms, .. } => arms.iter().map(|arm| &arm.body).collect(),
+            CompoundCommand::Conditional(_) | CompoundCommand::Arithmetic(_) => Vec::new(),
+        },
+        Command::Function { body, .. } => bodies(body),
+    }
+}
+
+/// The lists directly inside `command`: bodies, conditions and substitutions.
+fn nested_lists(command: &Command) -> Vec<&List> {
+    let mut words: Vec<&Word> = Vec::new();
+    match command {
+        Command::Simple(simple) => {
+            for assignment in &simple.assignments {
+                match &assignment.value {
+                    Value::Scalar(word) => words.push(word),
+                    Value::Array(items) => words.extend(items),
+                }
+            }
+            words.extend(&simple.words);
+        }
+        Command::Compound(compound, _) => match compound {
+            CompoundCommand::For { words: items, .. } => words.extend(items.iter().flatten()),
+            CompoundCommand::Case { word, arms } => {
+                words.push(word);
+                words.extend(arms.iter().flat_map(|arm| &arm.patterns));
+            }
+            CompoundCommand::Conditional(items) => words.extend(items),
+            _ => {}
+        },
+        Command::Function { body, .. } => return nested_lists(body),
+    }
+    for redirect in command.redirects() {
+        if let RedirectTarget::Word(word) = &redirect.target {
+            words.push(word);
+        }
+    }
+    let mut lists: Vec<&List> = words.into_iter().flat_map(Word::substitutions).collect();
+    lists.extend(bodies(command));
+    lists
+}
+
+fn is_meta(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+/// Whether unquoted text is a glob that a `(` continues: an extglob operator
+/// such as `@` or `!`, or a pattern that a zsh glob qualifier can follow.
+fn is_glob_prefix(literal: &str) -> bool {
+    literal.ends_with(['@', '!', '+', '*', '?']) || literal.contains(['*', '?', ']'])
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// After here-documents were read: the newline ending their line, and
+    /// where parsing continues once that newline is reached.
+    heredoc_resume: Option<(usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.rest().chars().nth(1)
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.rest().starts_with(text);
+        if found {
+            self.pos += text.len();
+        }
+        found
+    }
+
+    /// Whether the unquoted word `word` is next.
+    fn at_word(&self, word: &str) -> bool {
+        self.rest().starts_with(word) && self.rest()[word.len()..].chars().next().is_none_or(is_meta)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.at_word(word);
+        if found {
+            self.pos += word.len();
+        }
+        found
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        self.skip_linebreaks();
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", word)))
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), ParseError> {
+        self.skip_linebreaks();
+        if self.eat(text) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", text)))
+        }
+    }
+
+    /// Skips blanks, escaped newlines and comments, but not newlines.
+    fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r') => self.pos += 1,
+                Some('\\') if self.peek_second() == Some('\n') => self.pos += 2,
+                Some('#') => self.pos += self.rest().find('\n').unwrap_or(self.rest().len()),
+                _ => break,
+            }
+        }
+    }
+
+    /// Consumes a newline, skipping any here-document bodies that follow it.
+    fn newline(&mut self) -> bool {
+        if self.peek() != Some('\n') {
+            return false;
+        }
+        match self.heredoc_resume {
+            Some((newline, resume)) if newline == self.pos => {
+                self.pos = resume;
+                self.heredoc_resume = None;
+            }
+            _ => self.pos += 1,
+        }
+        true
+    }
+
+    fn skip_linebreaks(&mut self) {
+        self.skip_blanks();
+        while self.newline() {
+            self.skip_blanks();
+        }
+    }
+
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        let before = &self.src[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            message,
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    /// The token at the current position, for error messages.
+    fn found(&self) -> String {
+        let rest = self.rest();
+        match rest.chars().next() {
+            None => String::from("end of input"),
+            Some('\n') => String::from("newline"),
+            Some(c @ ('(' | ')')) => format!("'{}'", c),
+            Some(c) if is_meta(c) => {
+                format!("'{}'", rest.chars().take_while(|c| matches!(c, '|' | '&' | ';' | '<' | '>')).take(3).collect::<String>())
+            }
+            Some(_) => format!("'{}'", rest.chars().take_while(|&c| !is_meta(c)).collect::<String>()),
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        self.error(self.pos, format!("unexpected {}", self.found()))
+    }
+
+    fn expected(&self, what: &str) -> ParseError {
+        self.error(self.pos, format!("expected {}, found {}", what, self.found()))
+    }
+
+    fn at_list_end(&self, stops: &[&str]) -> bool {
+        let rest = self.rest();
+        rest.is_empty()
+            || rest.starts_with(')')
+            || rest.starts_with(";;")
+            || rest.starts_with(";&")
+            || stops.iter().any(|word| self.at_word(word))
+    }
+
+    /// Parses and-or lists until the end of input, a `)`, a `;;` or one of `stops`.
+    fn parse_list(&mut self, stops: &[&str]) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_linebreaks();
+            if self.at_list_end(stops) {
+                break;
+            }
+            let mut item = self.parse_and_or()?;
+            self.skip_blanks();
+            let rest = self.rest();
+            if rest.starts_with('&') && !rest.starts_with("&&") {
+                self.pos += 1;
+                item.background = true;
+                items.push(item);
+                continue;
+            }
+            items.push(item);
+            let rest = self.rest();
+            if rest.starts_with(';') && !rest.starts_with(";;") && !rest.starts_with(";&") {
+                self.pos += 1;
+            } else if self.peek() != Some('\n') {
+                break;
+            }
+        }
+        Ok(List { items })
+    }
+
+    /// Like `parse_list`, requiring at least one command.
+    fn parse_body(&mut self, stops: &[&str]) -> Result<List, ParseError> {
+        let list = self.parse_list(stops)?;
+        if list.items.is_empty() {
+            return Err(self.expected("a command"));
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            self.skip_blanks();
+            let connector = if self.eat("&&") {
+                Connector::And
+            } else if self.eat("||") {
+                Connector::Or
+            } else {
+                break;
+            };
+            self.skip_linebreaks();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok(AndOr { first, rest, background: false })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        self.skip_blanks();
+        let negated = self.eat_word("!");
+        let mut commands = vec![self.parse_command()?];
+        loop {
+            self.skip_blanks();
+            if self.rest().starts_with("||") || !(self.eat("|&") || self.eat("|")) {
+                break;
+            }
+            self.skip_linebreaks();
+            commands.push(self.parse_command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        self.skip_blanks();
+        if CLOSING_WORDS.iter().any(|word| self.at_word(word)) {
+            return Err(self.unexpected());
+        }
+
+        let compound = if self.eat_word("{") {
+            let body = self.parse_body(&["}"])?;
+            self.expect_word("}")?;
+            CompoundCommand::Group(body)
+        } else if self.eat("((") {
+            CompoundCommand::Arithmetic(self.arithmetic()?)
+        } else if self.eat("(") {
+            let body = self.parse_body(&[])?;
+            self.expect(")")?;
+            CompoundCommand::Subshell(body)
+        } else if self.eat_word("[[") {
+            self.conditional()?
+        } else if self.eat_word("if") {
+            self.if_clause()?
+        } else if self.at_word("while") || self.at_word("until") {
+            let until = self.eat_word("until");
+            self.eat_word("while");
+            let condition = self.parse_body(&["do"])?;
+            self.expect_word("do")?;
+            let body = self.parse_body(&["done"])?;
+            self.expect_word("done")?;
+            CompoundCommand::While { until, condition, body }
+        } else if self.at_word("for") || self.at_word("select") {
+            let select = self.eat_word("select");
+            self.eat_word("for");
+            self.for_clause(select)?
+        } else if self.eat_word("case") {
+            self.case_clause()?
+        } else if self.eat_word("function") {
+            self.skip_blanks();
+            let name = self.parse_word()?.ok_or_else(|| self.expected("a function name"))?.value();
+            self.skip_blanks();
+            if self.eat("(") {
+                self.expect(")")?;
+            }
+            return self.function_body(name);
+        } else {
+            return self.parse_simple();
+        };
+
+        let mut redirects = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.parse_redirect()? {
+                Some(redirect) => redirects.push(redirect),
+                None => break,
+            }
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_linebreaks();
+        let body = self.parse_command()?;
+        Ok(Command::Function { name, body: Box::new(body) })
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.parse_body(&["then"])?;
+            self.expect_word("then")?;
+            let body = self.parse_body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            if self.eat_word("elif") {
+                continue;
+            }
+            if self.eat_word("else") {
+                otherwise = Some(self.parse_body(&["fi"])?);
+            }
+            self.expect_word("fi")?;
+            return Ok(CompoundCommand::If { branches, otherwise });
+        }
+    }
+
+    fn for_clause(&mut self, select: bool) -> Result<CompoundCommand, ParseError> {
+        self.skip_blanks();
+        if !select && self.eat("((") {
+            let header = self.arithmetic()?;
+            self.skip_blanks();
+            self.eat(";");
+            self.expect_word("do")?;
+            let body = self.parse_body(&["done"])?;
+            self.expect_word("done")?;
+            return Ok(CompoundCommand::ArithmeticFor { header, body });
+        }
+
+        let start = self.pos;
+        let Some(variable) = self.parse_word()?.and_then(|word| word.literal()).filter(|name| is_name(name)) else {
+            self.pos = start;
+            return Err(self.expected(if select { "a variable name after 'select'" } else { "a variable name after 'for'" }));
+        };
+        self.skip_linebreaks();
+        let words = if self.eat_word("in") {
+            let mut words = Vec::new();
+            loop {
+                self.skip_blanks();
+                match self.parse_word()? {
+                    Some(word) => words.push(word),
+                    None => break,
+                }
+            }
+            Some(words)
+        } else {
+            None
+        };
+        self.skip_blanks();
+        if !self.rest().starts_with(";;") {
+            self.eat(";");
+        }
+        self.expect_word("do")?;
+        let body = self.parse_body(&["done"])?;
+        self.expect_word("done")?;
+        Ok(CompoundCommand::For { select, variable, words, body })
+    }
+
+    fn case_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.skip_blanks();
+        let word = self.parse_word()?.ok_or_else(|| self.expected("a word after 'case'"))?;
+        self.expect_word("in")?;
+        let mut arms = Vec::new();
+        loop {
+            self.skip_linebreaks();
+            if self.eat_word("esac") {
+                break;
+            }
+            self.eat("(");
+            let mut patterns = Vec::new();
+            loop {
+                self.skip_blanks();
+                patterns.push(self.parse_word()?.ok_or_else(|| self.expected("a pattern"))?);
+                self.skip_blanks();
+                if !self.eat("|") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+            let body = self.parse_list(&["esac"])?;
+            arms.push(CaseArm { patterns, body });
+            if !(self.eat(";;&") || self.eat(";;") || self.eat(";&")) {
+                self.expect_word("esac")?;
+                break;
+            }
+        }
+        Ok(CompoundCommand::Case { word, arms })
+    }
+
+    /// The inside of `[[ ... ]]`, where `<`, `>`, `&&`, `||` and parentheses are operands.
+    fn conditional(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut words = Vec::new();
+        loop {
+            self.skip_linebreaks();
+            if self.eat_word("]]") {
+                return Ok(CompoundCommand::Conditional(words));
+            }
+            let start = self.pos;
+            let operator: String = self.rest().chars().take_while(|c| matches!(c, '<' | '>' | '&' | '|' | '(' | ')')).take(2).collect();
+            if !operator.is_empty() {
+                self.pos += operator.len();
+                words.push(Word { parts: vec![WordPart::Literal(operator)], span: start..self.pos });
+                continue;
+            }
+            match self.parse_word()? {
+                Some(word) => words.push(word),
+                None => return Err(self.expected("']]'")),
+            }
+        }
+    }
+
+    /// Reads up to the `))` closing an arithmetic expression whose `((` was consumed.
+    fn arithmetic(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    if self.rest()[i + 1..].starts_with(')') {
+                        let expression = self.rest()[..i].to_string();
+                        self.pos += i + 2;
+                        return Ok(expression);
+                    }
+                    break;
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(self.error(start, String::from("unterminated arithmetic expression, expected '))'")))
+    }
+
+    fn parse_simple(&mut self) -> Result<Command, ParseError> {
+        let start = self.pos;
+        let mut end = start;
+        let mut command = SimpleCommand { assignments: Vec::new(), words: Vec::new(), redirects: Vec::new(), span: 0..0 };
+        loop {
+            self.skip_blanks();
+            let Some(c) = self.peek() else { break };
+            let process_substitution = matches!(c, '<' | '>') && self.peek_second() == Some('(');
+            if !process_substitution {
+                if let Some(redirect) = self.parse_redirect()? {
+                    end = redirect.span.end;
+                    command.redirects.push(redirect);
+                    continue;
+                }
+            }
+            if c == '(' && command.words.len() == 1 && command.assignments.is_empty() && command.redirects.is_empty() {
+                self.pos += 1;
+                self.expect(")")?;
+                let name = command.words[0].value();
+                return self.function_body(name);
+            }
+            if is_meta(c) && !process_substitution {
+                break;
+            }
+            let Some(word) = self.parse_word()? else { break };
+            end = word.span.end;
+            if command.words.is_empty() {
+                if let Some(assignment) = self.assignment(&word)? {
+                    end = assignment.span.end;
+                    command.assignments.push(assignment);
+                    continue;
+                }
+            }
+            command.words.push(word);
+        }
+        if command.words.is_empty() && command.assignments.is_empty() && command.redirects.is_empty() {
+            return Err(self.expected("a command"));
+        }
+        command.span = start..end;
+        Ok(Command::Simple(command))
+    }
+
+    /// Reads `word` as an assignment if it starts with `name=`.
+    fn assignment(&mut self, word: &Word) -> Result<Option<Assignment>, ParseError> {
+        let Some(WordPart::Literal(first)) = word.parts.first() else {
+            return Ok(None);
+        };
+        let Some((name, value)) = first.split_once('=') else {
+            return Ok(None);
+        };
+        let name = name.strip_suffix('+').unwrap_or(name);
+        if !is_name(name) {
+            return Ok(None);
+        }
+
+        let mut parts = word.parts[1..].to_vec();
+        if !value.is_empty() {
+            parts.insert(0, WordPart::Literal(value.to_string()));
+        }
+        let value_start = word.span.start + first.find('=').unwrap_or(0) + 1;
+        let value = if parts.is_empty() && self.peek() == Some('(') {
+            self.pos += 1;
+            let mut items = Vec::new();
+            loop {
+                self.skip_linebreaks();
+                if self.eat(")") {
+                    break;
+                }
+                items.push(self.parse_word()?.ok_or_else(|| self.expected("')'"))?);
+            }
+            Value::Array(items)
+        } else {
+            Value::Scalar(Word { parts, span: value_start..word.span.end })
+        };
+        Ok(Some(Assignment { name: name.to_string(), value, span: word.span.start..self.pos.max(word.span.end) }))
+    }
+
+    fn parse_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let fd = match rest[digits..].chars().next() {
+            Some('<' | '>') if digits > 0 => rest[..digits].parse().ok(),
+            _ => None,
+        };
+        let skip = if fd.is_some() { digits } else { 0 };
+        let Some(&(operator, op)) = REDIRECT_OPERATORS
+            .iter()
+            .find(|(operator, _)| rest[skip..].starts_with(operator) && !(fd.is_some() && operator.starts_with('&')))
+        else {
+            return Ok(None);
+        };
+        self.pos += skip + operator.len();
+        self.skip_blanks();
+
+        let word = self.parse_word()?.ok_or_else(|| self.expected(&format!("a target after '{}'", operator)))?;
+        let target = if op == RedirectOp::HereDoc {
+            let delimiter = word.value();
+            let strip_tabs = operator == "<<-";
+            let (body, span) = self.here_doc_body(&delimiter, strip_tabs);
+            RedirectTarget::HereDoc(HereDoc { quoted: word.is_quoted(self.src), delimiter, strip_tabs, body, span })
+        } else {
+            RedirectTarget::Word(word)
+        };
+        Ok(Some(Redirect { fd, op, target, span: start..self.pos }))
+    }
+
+    /// Reads the body of a here-document, which starts on the line after the
+    /// current one (or after the previous here-document on this line), and
+    /// arranges for parsing to skip it. An unterminated body runs to the end.
+    fn here_doc_body(&mut self, delimiter: &str, strip_tabs: bool) -> (String, Range<usize>) {
+        let (newline, mut cursor) = match self.heredoc_resume {
+            Some(resume) => resume,
+            None => match self.rest().find('\n') {
+                Some(i) => (self.pos + i, self.pos + i + 1),
+                None => return (String::new(), self.src.len()..self.src.len()),
+            },
+        };
+        let start = cursor;
+        let mut body = String::new();
+        while cursor < self.src.len() {
+            let end = self.src[cursor..].find('\n').map_or(self.src.len(), |i| cursor + i);
+            let line = &self.src[cursor..end];
+            cursor = (end + 1).min(self.src.len());
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+            if line == delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        self.heredoc_resume = Some((newline, cursor));
+        (body, start..cursor)
+    }
+
+    /// Reads a word, or returns `None` at an operator, newline or the end.
+    fn parse_word(&mut self) -> Result<Option<Word>, ParseError> {
+        let start = self.pos;
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '<' | '>' if self.pos == start && self.peek_second() == Some('(') => {
+                    parts.push(self.process_substitution(c == '>')?);
+                }
+                // bash's extglob `!(*.txt)` and zsh's glob qualifiers `*(.)` stay part of the pattern
+                '(' if is_glob_prefix(&literal) && self.peek_second() != Some(')') => {
+                    let group = self.glob_group()?;
+                    literal.push_str(group);
+                }
+                c if is_meta(c) => break,
+                '\'' => {
+                    flush(&mut literal, &mut parts);
+                    let end = self.rest()[1..]
+                        .find('\'')
+                        .ok_or_else(|| self.error(self.pos, String::from("unterminated single quote")))?;
+                    parts.push(WordPart::SingleQuoted(self.rest()[1..=end].to_string()));
+                    self.pos += end + 2;
+                }
+                '"' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.double_quoted()?));
+                }
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.pos += 1,
+                        Some(escaped) => {
+                            literal.push(escaped);
+                            self.pos += escaped.len_utf8();
+                        }
+                        None => literal.push('\\'),
+                    }
+                }
+                '$' => match self.dollar(false)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => {
+                        literal.push('$');
+                        self.pos += 1;
+                    }
+                },
+                '`' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted()?);
+                }
+                c => {
+                    literal.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok((self.pos > start).then_some(Word { parts, span: start..self.pos }))
+    }
+
+    /// A parenthesized group that continues a glob pattern, as written.
+    fn glob_group(&mut self) -> Result<&'a str, ParseError> {
+        let start = self.pos;
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += i + 1;
+                        return Ok(&self.src[start..self.pos]);
+                    }
+                }
+                '\n' => break,
+                _ => {}
+            }
+        }
+        Err(self.error(start, String::from("unterminated glob pattern '('")))
+    }
+
+    fn double_quoted(&mut self) -> Result<Vec<WordPart>, ParseError> {
+        let open = self.pos;
+        self.pos += 1;
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error(open, String::from("unterminated double quote"))),
+                Some('"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.pos += 1,
+                        Some(escaped @ ('"' | '\\' | '$' | '`')) => {
+                            literal.push(escaped);
+                            self.pos += 1;
+                        }
+                        _ => literal.push('\\'),
+                    }
+                }
+                Some('$') => match self.dollar(true)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => {
+                        literal.push('$');
+                        self.pos += 1;
+                    }
+                },
+                Some('`') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted()?);
+                }
+                Some(c) => {
+                    literal.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(parts)
+    }
+
+    /// Reads the expansion starting with the `$` at the current position, or
+    /// returns `None` when the `$` is literal.
+    fn dollar(&mut self, in_double_quotes: bool) -> Result<Option<WordPart>, ParseError> {
+        let start = self.pos;
+        let rest = &self.rest()[1..];
+        if rest.starts_with("((") {
+            self.pos += 3;
+            return Ok(Some(WordPart::Arithmetic(self.arithmetic()?)));
+        }
+        if rest.starts_with('(') {
+            self.pos += 2;
+            let body = self.parse_list(&[])?;
+            self.expect(")")?;
+            return Ok(Some(WordPart::CommandSubstitution { text: self.src[start..self.pos].to_string(), body }));
+        }
+        if rest.starts_with('{') {
+            // Braces inside quotes, as in ${x:-"}"}, don't close the expansion.
+            let mut depth = 0;
+            let mut quote = None;
+            let mut chars = rest.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match (quote, c) {
+                    (Some('\''), '\'') => quote = None,
+                    (Some('\''), _) => {}
+                    (_, '\\') => {
+                        chars.next();
+                    }
+                    (Some('"'), '"') => quote = None,
+                    (None, '"') => quote = Some('"'),
+                    (None, '\'') if !in_double_quotes => quote = Some('\''),
+                    (None, '{') => depth += 1,
+                    (None, '}') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            self.pos += i + 2;
+                            return Ok(Some(WordPart::Parameter(self.src[start..self.pos].to_string())));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            return Err(self.error(start, String::from("unterminated '${'")));
+        }
+        if !in_double_quotes && rest.starts_with('\'') {
+            // bash's $'...', where backslash escapes a quote
+            let mut escaped = false;
+            for (i, c) in rest.char_indices().skip(1) {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '\'' if !escaped => {
+                        let text = rest[1..i].to_string();
+                        self.pos += i + 2;
+                        return Ok(Some(WordPart::SingleQuoted(text)));
+                    }
+                    _ => escaped = false,
+                }
+            }
+            return Err(self.error(start, String::from("unterminated single quote")));
+        }
+        if !in_double_quotes && rest.starts_with('"') {
+            self.pos += 1;
+            return Ok(Some(WordPart::DoubleQuoted(self.double_quoted()?)));
+        }
+        let length = match rest.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count()
+            }
+            Some(c) if c.is_ascii_digit() || matches!(c, '@' | '*' | '#' | '?' | '$' | '!' | '-') => 1,
+            _ => return Ok(None),
+        };
+        self.pos += 1 + length;
+        Ok(Some(WordPart::Parameter(self.src[start..self.pos].to_string())))
+    }
+
+    fn backquoted(&mut self) -> Result<WordPart, ParseError> {
+        let start = self.pos;
+        let mut inner = String::new();
+        let mut chars = self.rest()[1..].char_indices();
+        let end = loop {
+            match chars.next() {
+                None => return Err(self.error(start, String::from("unterminated '`'"))),
+                Some((i, '`')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, c @ ('`' | '\\' | '$'))) => inner.push(c),
+                    Some((_, c)) => {
+                        inner.push('\\');
+                        inner.push(c);
+                    }
+                    None => inner.push('\\'),
+                },
+                Some((_, c)) => inner.push(c),
+            }
+        };
+        self.pos += end + 2;
+        let body = parse(&inner).map_err(|e| self.error(start, format!("in command substitution: {}", e.message)))?;
+        Ok(WordPart::CommandSubstitution { text: self.src[start..self.pos].to_string(), body })
+    }
+
+    fn process_substitution(&mut self, output: bool) -> Result<WordPart, ParseError> {
+        let start = self.pos;
+        self.pos += 2;
+        let body = self.parse_list(&[])?;
+        self.expect(")")?;
+        Ok(WordPart::ProcessSubstitution { output, text: self.src[start..self.pos].to_string(), body })
+    }
+}
+
+fn flush(literal: &mut String, parts: &mut Vec<WordPart>) {
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
@@ -0,0 +1,462 @@
+//! Snapshots of the files in the current directory that a command is about to
+//! change, kept in toli's data directory so `toli undo` can put them back.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::classify::{self, Category};
+use crate::config;
+use crate::normalize;
+use crate::sandbox::{self, ChangeKind};
+use crate::shell::{self, Command, RedirectTarget, SimpleCommand, Word};
+use crate::usage;
+
+/// Snapshots kept; older ones are removed when a new one is taken.
+pub const KEEP: usize = 20;
+/// Snapshots that would need more than this many bytes of copies are not taken.
+pub const MAX_SNAPSHOT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Programs whose operands name the files they change. Other programs rated
+/// as changing files may change anything in the current directory.
+const PATH_WRITERS: &[&str] = &[
+    "rm", "rmdir", "unlink", "mv", "cp", "ln", "install", "touch", "mkdir", "truncate", "shred", "chmod",
+    "chown", "chgrp", "tee", "sed", "dd",
+];
+/// Programs that only remove or rename their operands, so a hard link keeps the original.
+const UNLINKERS: &[&str] = &["rm", "rmdir", "unlink", "mv"];
+
+/// A path a command may change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Affected {
+    pub path: PathBuf,
+    /// The command only removes or renames it, so the snapshot can hard-link
+    /// it rather than copy it.
+    pub unlinked_only: bool,
+}
+
+/// The paths below `cwd` that `command` may create, change or remove, as
+/// far as `classify` rates its parts as changing files. Operands that can't be
+/// resolved, and programs that don't name what they change, affect all of `cwd`.
+pub fn affected_paths(command: &str, cwd: &Path) -> Vec<Affected> {
+    let Ok(list) = shell::parse(command) else {
+        return Vec::new();
+    };
+    let mut affected = Vec::new();
+    for pipeline in list.pipelines() {
+        for command in &pipeline.commands {
+            for redirect in command.redirects() {
+                if let RedirectTarget::Word(target) = &redirect.target {
+                    if classify::assess_redirect(redirect).1 == Category::Filesystem {
+                        add(&mut affected, resolve(target.literal().as_deref(), cwd), false);
+                    }
+                }
+            }
+            if let Command::Simple(simple) = command {
+                add_operands(simple, cwd, &mut affected);
+            }
+        }
+    }
+
+    affected.retain(|a| a.path.starts_with(cwd));
+    affected.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut tidy: Vec<Affected> = Vec::new();
+    for a in affected {
+        match tidy.last_mut() {
+            Some(last) if a.path.starts_with(&last.path) => last.unlinked_only &= a.unlinked_only,
+            _ => tidy.push(a),
+        }
+    }
+    tidy
+}
+
+fn add(affected: &mut Vec<Affected>, paths: Vec<PathBuf>, unlinked_only: bool) {
+    affected.extend(paths.into_iter().map(|path| Affected { path, unlinked_only }));
+}
+
+fn add_operands(command: &SimpleCommand, cwd: &Path, affected: &mut Vec<Affected>) {
+    let values: Vec<String> = command.words.iter().map(Word::value).collect();
+    let words: Vec<&str> = values.iter().map(String::as_str).collect();
+    // Look through `sudo`, `xargs` and the like, as `classify` does.
+    let start = shell::wrapped_program(&words);
+    let Some(program) = words.get(start).map(|p| p.rsplit('/').next().unwrap_or(p)) else {
+        return;
+    };
+    match classify::assess_simple(&words).1 {
+        Category::Filesystem => {}
+        // A program toli doesn't know may change the files it is given.
+        Category::Other if names_files(&command.words[start + 1..], cwd) => {
+            add(affected, vec![cwd.to_path_buf()], false);
+            return;
+        }
+        _ => return,
+    }
+    // `xargs` adds operands read from its input.
+    let from_input = words[..start].iter().any(|w| w.rsplit('/').next() == Some("xargs"));
+    if !PATH_WRITERS.contains(&program) || from_input {
+        add(affected, vec![cwd.to_path_buf()], false);
+        return;
+    }
+
+    let value_flags = normalize::value_flags(program).unwrap_or("");
+    let mut flags = Vec::new();
+    let mut operands = Vec::new();
+    let mut options_done = false;
+    let mut rest = command.words.iter().zip(&words).skip(start + 1);
+    while let Some((word, value)) = rest.next() {
+        if !options_done && *value == "--" {
+            options_done = true;
+        } else if !options_done && value.starts_with('-') && value.len() > 1 {
+            flags.push(*value);
+            // A flag ending its bundle with a letter that takes a value, like
+            // `-s` in `truncate -s 0 log`, takes the next word.
+            let letters = value.strip_prefix('-').filter(|letters| !letters.starts_with('-')).unwrap_or("");
+            if letters.find(|c| value_flags.contains(c)).is_some_and(|at| at + 1 == letters.len()) {
+                rest.next();
+            }
+        } else {
+            operands.push(word.literal());
+        }
+    }
+    let unlinked_only = UNLINKERS.contains(&program);
+    match program {
+        "dd" => {
+            for operand in operands.iter().flatten() {
+                if let Some(output) = operand.strip_prefix("of=") {
+                    add(affected, resolve(Some(output), cwd), false);
+                }
+            }
+        }
+        // The sources are left alone; the target is the last operand or,
+        // when that is a directory, the entries for the sources inside it.
+        "cp" | "ln" | "install" | "mv" => {
+            let Some((target, sources)) = operands.split_last() else { return };
+            let targets = resolve(target.as_deref(), cwd);
+            match targets.as_slice() {
+                [dir] if dir.is_dir() && dir != cwd => {
+                    for source in sources {
+                        let names = resolve(source.as_deref(), cwd);
+                        add(affected, names.iter().filter_map(|s| s.file_name()).map(|n| dir.join(n)).collect(), false);
+                    }
+                }
+                _ => add(affected, targets, false),
+            }
+            if program == "mv" {
+                for source in sources {
+                    add(affected, resolve(source.as_deref(), cwd), true);
+                }
+            }
+        }
+        _ => {
+            // The mode, owner or sed script comes before the files.
+            let skip = match program {
+                "chmod" | "chown" | "chgrp" => !flags.iter().any(|f| f.starts_with("--reference")),
+                "sed" => !flags.iter().any(|f| f.starts_with("-e") || f.starts_with("-f") || f.starts_with("--expression") || f.starts_with("--file")),
+                _ => false,
+            };
+            for operand in operands.iter().skip(usize::from(skip)) {
+                add(affected, resolve(operand.as_deref(), cwd), unlinked_only);
+            }
+        }
+    }
+}
+
+/// Whether any of `args` that isn't a flag is a pattern or names an existing
+/// path below `cwd`.
+fn names_files(args: &[Word], cwd: &Path) -> bool {
+    args.iter().filter_map(Word::literal).filter(|arg| !arg.starts_with('-')).any(|arg| {
+        resolve(Some(&arg), cwd)
+            .iter()
+            .any(|path| path.starts_with(cwd) && (arg.contains(['*', '?', '[']) || path.exists()))
+    })
+}
+
+/// The paths an operand stands for, with `*` and `?` in its last component
+/// matched against the directory. Operands with expansions, or patterns this
+/// doesn't match, stand for all of `cwd` or the directory holding the pattern.
+fn resolve(operand: Option<&str>, cwd: &Path) -> Vec<PathBuf> {
+    let Some(operand) = operand else {
+        return vec![cwd.to_path_buf()];
+    };
+    let mut path = PathBuf::new();
+    for component in cwd.join(config::expand_home(Path::new(operand))).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            component => path.push(component),
+        }
+    }
+    let is_pattern = |c: &Component| c.as_os_str().to_string_lossy().contains(['*', '?', '[']);
+    let components: Vec<Component> = path.components().collect();
+    let Some(first) = components.iter().position(is_pattern) else {
+        return vec![path];
+    };
+    let dir: PathBuf = components[..first].iter().collect();
+    let pattern = components[first].as_os_str().to_string_lossy().into_owned();
+    if first + 1 < components.len() || pattern.contains('[') {
+        return vec![dir];
+    }
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| (pattern.starts_with('.') || !name.starts_with('.')) && wildcard(&pattern, name))
+        .map(|name| dir.join(name))
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Whether `name` matches a pattern of literal characters, `*` and `?`.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The state of some paths before a command ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub command: String,
+    pub cwd: PathBuf,
+    pub entries: Vec<Entry>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// A path that restoring a snapshot puts back or removes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Relative to `cwd`; empty for `cwd` itself.
+    pub path: PathBuf,
+    /// Whether the path is put back; otherwise it was created since and is removed.
+    pub existed: bool,
+    stored: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Relative to `cwd`; empty for `cwd` itself.
+    pub path: PathBuf,
+    /// Paths that didn't exist are removed when the snapshot is restored.
+    pub existed: bool,
+}
+
+/// Snapshots, one directory each with a `snapshot.json` manifest and the
+/// saved entries under `files/`.
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The store in `undo` in toli's data directory.
+    pub fn open() -> Result<Self> {
+        Ok(Self::new(
+            dirs::data_dir().ok_or_else(|| anyhow!("Could not determine the data directory"))?.join("toli").join("undo"),
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Saves `affected` as they are now, before `command` runs in `cwd`. The
+    /// store itself is left out when it lies below `cwd`.
+    pub fn save(&self, command: &str, cwd: &Path, affected: &[Affected]) -> Result<Snapshot> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = self.dir.join(format!("{:020}-{}", nanos, std::process::id()));
+        std::fs::create_dir_all(dir.join("files")).map_err(|e| anyhow!("Failed to create {}: {}", dir.display(), e))?;
+        let store = self.dir.canonicalize().unwrap_or_else(|_| self.dir.clone());
+
+        let mut budget = MAX_SNAPSHOT_BYTES;
+        let mut entries = Vec::new();
+        let affected = affected.iter().filter(|a| !a.path.starts_with(&store));
+        let saved = affected.enumerate().try_for_each(|(i, a)| {
+            let existed = std::fs::symlink_metadata(&a.path).is_ok();
+            if existed {
+                let target = dir.join("files").join(i.to_string());
+                preserve(&a.path, &target, a.unlinked_only, Some(&store), &mut budget)?;
+            }
+            let path = a.path.strip_prefix(cwd).unwrap_or(&a.path).to_path_buf();
+            entries.push(Entry { path, existed });
+            Ok::<_, anyhow::Error>(())
+        });
+        let snapshot = Snapshot { timestamp: usage::now(), command: command.to_string(), cwd: cwd.to_path_buf(), entries, dir };
+        // The manifest is written last, so a failed snapshot is never restored.
+        let saved = saved.and_then(|_| Ok(std::fs::write(snapshot.dir.join("snapshot.json"), serde_json::to_string_pretty(&snapshot)?)?));
+        if let Err(e) = saved {
+            std::fs::remove_dir_all(&snapshot.dir).ok();
+            return Err(e);
+        }
+
+        let ids = self.ids()?;
+        for old in &ids[..ids.len().saturating_sub(KEEP)] {
+            std::fs::remove_dir_all(self.dir.join(old)).ok();
+        }
+        Ok(snapshot)
+    }
+
+    /// The most recent snapshot.
+    pub fn latest(&self) -> Result<Option<Snapshot>> {
+        for id in self.ids()?.into_iter().rev() {
+            let dir = self.dir.join(id);
+            if let Ok(manifest) = std::fs::read_to_string(dir.join("snapshot.json")) {
+                let snapshot: Snapshot = serde_json::from_str(&manifest)
+                    .map_err(|e| anyhow!("Failed to read snapshot {}: {}", dir.display(), e))?;
+                return Ok(Some(Snapshot { dir, ..snapshot }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Snapshot directory names, oldest first.
+    fn ids(&self) -> Result<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+        let mut ids: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+impl Snapshot {
+    /// What restoring the snapshot does. Of an entry that was a directory and
+    /// still is, only the paths below it that changed since are put back or
+    /// removed, so files nothing touched are left alone.
+    pub fn steps(&self) -> Result<Vec<Step>> {
+        let store = self.dir.parent().and_then(|dir| dir.canonicalize().ok());
+        let mut steps = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let stored = self.dir.join("files").join(i.to_string());
+            let path = self.cwd.join(&entry.path);
+            if !(entry.existed && is_dir(&stored) && is_dir(&path)) {
+                steps.push(Step { path: entry.path.clone(), existed: entry.existed, stored });
+                continue;
+            }
+            // A directory that is put back or removed takes what is below it along.
+            let mut handled: Option<PathBuf> = None;
+            for change in sandbox::changes(&stored, &path, store.as_deref())? {
+                if handled.as_ref().is_some_and(|dir| change.path.starts_with(dir)) {
+                    continue;
+                }
+                if change.kind != ChangeKind::Modified || !is_dir(&stored.join(&change.path)) {
+                    handled = Some(change.path.clone());
+                }
+                steps.push(Step {
+                    path: entry.path.join(&change.path),
+                    existed: change.kind != ChangeKind::Created,
+                    stored: stored.join(&change.path),
+                });
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Takes the [`steps`](Self::steps) that put the paths back as they were
+    /// and removes the snapshot.
+    pub fn restore(self) -> Result<()> {
+        for step in self.steps()? {
+            let path = self.cwd.join(&step.path);
+            if step.existed {
+                replace(&step.stored, &path)?;
+            } else {
+                remove(&path)?;
+            }
+        }
+        std::fs::remove_dir_all(&self.dir).map_err(|e| anyhow!("Failed to remove {}: {}", self.dir.display(), e))
+    }
+}
+
+fn is_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Saves `source` at `target`, hard-linking files when `link` is set and
+/// copying them otherwise, within `budget` bytes of copies. `excluded` is
+/// left out wherever it turns up.
+fn preserve(source: &Path, target: &Path, link: bool, excluded: Option<&Path>, budget: &mut u64) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    if metadata.file_type().is_symlink() {
+        sandbox::symlink(&std::fs::read_link(source)?, target)?;
+    } else if metadata.is_dir() {
+        std::fs::create_dir(target)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            if Some(entry.path().as_path()) != excluded {
+                preserve(&entry.path(), &target.join(entry.file_name()), link, excluded, budget)?;
+            }
+        }
+        std::fs::set_permissions(target, metadata.permissions())?;
+    } else if !(link && std::fs::hard_link(source, target).is_ok()) {
+        *budget = budget.checked_sub(metadata.len()).ok_or_else(|| {
+            anyhow!("the files would take more than {} MB to copy", MAX_SNAPSHOT_BYTES / 1024 / 1024)
+        })?;
+        std::fs::copy(source, target).map_err(|e| anyhow!("Failed to copy {}: {}", source.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Replaces `path` with the saved `stored`. Of a directory restored over a
+/// directory only the permissions are put back; its contents are steps of their own.
+fn replace(stored: &Path, path: &Path) -> Result<()> {
+    let mut unlimited = u64::MAX;
+    if is_dir(stored) && is_dir(path) {
+        std::fs::set_permissions(path, std::fs::metadata(stored)?.permissions())?;
+    } else {
+        remove(path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        preserve(stored, path, false, None, &mut unlimited)
+            .map_err(|e| anyhow!("Failed to restore {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let result = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(_) => Ok(()),
+    };
+    result.map_err(|e| anyhow!("Failed to remove {}: {}", path.display(), e))
+}
@@ -0,0 +1,234 @@
+//! Token usage reported by providers, the local ledger it is recorded in, and
+//! cost estimates from a price table.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::config;
+
+/// Built-in prices in US dollars per million input and output tokens.
+/// Entries in `usage.prices` are added to these and take precedence.
+const BUILTIN_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4o", 2.50, 10.0),
+    ("gpt-4o-mini", 0.15, 0.60),
+];
+
+/// Token counts reported by a provider for one request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Time spent loading the model, for providers that report it (Ollama).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_ms: Option<u64>,
+    /// Time spent reading the prompt, for providers that report it (Ollama).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_eval_ms: Option<u64>,
+    /// Time spent generating the reply, for providers that report it (Ollama).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eval_ms: Option<u64>,
+    /// Time the provider spent on the whole request, loading included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+}
+
+/// One request to a model, as kept in the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub backend: String,
+    pub model: String,
+    /// The task, e.g. `translate` or `explain`.
+    pub task: String,
+    /// The prompt templates used, e.g. `v1`; empty in older records.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prompt_version: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Wall-clock time of the request.
+    pub elapsed_ms: u64,
+}
+
+/// Price of a model in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// The `[usage]` section: where usage is recorded and what it costs.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// Ledger location; defaults to `usage.jsonl` in toli's data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ledger: Option<PathBuf>,
+    /// Hosted backends are not used once this month's estimated cost reaches this many dollars.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_budget: Option<f64>,
+    /// Prices by model name.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub prices: HashMap<String, Price>,
+}
+
+impl UsageConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The price of `model`, if known. Local Ollama models are free unless priced explicitly.
+    pub fn price(&self, backend: &str, model: &str) -> Option<Price> {
+        self.prices
+            .get(model)
+            .copied()
+            .or_else(|| {
+                BUILTIN_PRICES
+                    .iter()
+                    .find(|(name, _, _)| *name == model)
+                    .map(|&(_, input, output)| Price { input, output })
+            })
+            .or_else(|| (backend == "Ollama").then_some(Price { input: 0.0, output: 0.0 }))
+    }
+
+    /// Estimated cost of `record` in dollars, or `None` when its model has no price.
+    pub fn cost(&self, record: &UsageRecord) -> Option<f64> {
+        let price = self.price(&record.backend, &record.model)?;
+        Some(
+            (record.usage.prompt_tokens as f64 * price.input + record.usage.completion_tokens as f64 * price.output)
+                / 1_000_000.0,
+        )
+    }
+
+    pub fn ledger_path(&self) -> Result<PathBuf> {
+        match &self.ledger {
+            Some(path) => Ok(config::expand_home(path)),
+            None => Ok(dirs::data_dir()
+                .ok_or_else(|| anyhow!("Could not determine the data directory"))?
+                .join("toli")
+                .join("usage.jsonl")),
+        }
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(budget) = self.monthly_budget {
+            if budget < 0.0 {
+                return Err(format!("usage.monthly_budget must not be negative, got {}", budget));
+            }
+        }
+        for (model, price) in &self.prices {
+            if price.input < 0.0 || price.output < 0.0 {
+                return Err(format!("usage.prices.\"{}\" must not be negative", model));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage records, one JSON object per line.
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn from_config(config: &UsageConfig) -> Result<Self> {
+        Ok(Self::new(config.ledger_path()?))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, records: &[UsageRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .map_err(|e| anyhow!("Failed to write usage ledger {}: {}", self.path.display(), e))
+    }
+
+    /// All records, oldest first. Lines that can't be read are skipped.
+    pub fn records(&self) -> Result<Vec<UsageRecord>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("Failed to read usage ledger {}: {}", self.path.display(), e)),
+        };
+        Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+/// Sums over a set of records.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Estimated cost of the records whose model has a price.
+    pub cost: f64,
+    /// Records left out of `cost` because their model has no price.
+    pub unpriced: u64,
+}
+
+impl Totals {
+    pub fn add(&mut self, record: &UsageRecord, config: &UsageConfig) {
+        self.requests += 1;
+        self.prompt_tokens += record.usage.prompt_tokens;
+        self.completion_tokens += record.usage.completion_tokens;
+        match config.cost(record) {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+/// Estimated spend in the calendar month (UTC) containing `now`.
+pub fn spent_in_month(records: &[UsageRecord], config: &UsageConfig, now: u64) -> f64 {
+    let month = &date(now)[..7];
+    let mut totals = Totals::default();
+    for record in records.iter().filter(|r| date(r.timestamp).starts_with(month)) {
+        totals.add(record, config);
+    }
+    totals.cost
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// The UTC date of a Unix timestamp as `YYYY-MM-DD`.
+pub fn date(timestamp: u64) -> String {
+    // Days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,597 @@
+mod common;
+
+use common::{command_output, stderr, stdout, StubServer, TestEnv};
+use serde_json::json;
+
+fn ollama_config(endpoint: &str) -> String {
+    format!(
+        "config_version = 1\nbackend = \"Ollama\"\nadditional_context = \"test env\"\n\n[ollama]\nendpoint = \"{}\"\nmodel = \"test-model\"\n",
+        endpoint
+    )
+}
+
+fn openai_config(endpoint: &str) -> String {
+    format!(
+        "config_version = 1\nbackend = \"OpenAI\"\nadditional_context = \"test env\"\n\n[openai]\napi_key = \"sk-test-key\"\nmodel = \"gpt-test\"\nendpoint = \"{}\"\n",
+        endpoint
+    )
+}
+
+fn ollama_reply(text: &str) -> (u16, String) {
+    (200, json!({ "model": "test-model", "message": { "role": "assistant", "content": text }, "done": true }).to_string())
+}
+
+fn openai_reply(content: &str) -> (u16, String) {
+    (200, json!({ "choices": [{ "message": { "role": "assistant", "content": content } }] }).to_string())
+}
+
+/// Ollama at `ollama`, falling back to OpenAI at `openai`.
+fn fallback_config(ollama: &str, openai: &str) -> String {
+    format!(
+        "config_version = 1\nbackend = [\"Ollama\", \"OpenAI\"]\nbackend_timeout = 1\n\n[ollama]\nendpoint = \"{}\"\nmodel = \"test-model\"\n\n[openai]\napi_key = \"sk-test-key\"\nmodel = \"gpt-test\"\nendpoint = \"{}\"\n",
+        ollama, openai
+    )
+}
+
+fn unused_port_url() -> String {
+    // Bind and drop a listener to get a port nothing is listening on.
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    format!("http://127.0.0.1:{}", port)
+}
+
+const LS_OPTIONS: &str = r#"[{"command": "ls -la", "explanation": "Lists files", "confidence": 0.9}]"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_translates_query() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list", "files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) ls -la"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    let body = requests[0].json();
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["messages"][0]["role"], "system");
+    assert!(body["messages"][0]["content"].as_str().unwrap().contains("test env"));
+    assert_eq!(body["messages"][1]["role"], "user");
+    assert!(body["messages"][1]["content"].as_str().unwrap().contains("list files"));
+    assert!(!body["messages"][0]["content"].as_str().unwrap().contains("list files"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_can_use_generate_api() {
+    let reply = json!({ "model": "test-model", "response": LS_OPTIONS, "done": true }).to_string();
+    let server = StubServer::start(vec![("/api/generate", vec![(200, reply)])]).await;
+    let config = ollama_config(&server.url).replace("model = \"test-model\"\n", "model = \"test-model\"\napi = \"generate\"\n");
+    let env = TestEnv::new(&config);
+
+    let output = env.run(&["list", "files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) ls -la"));
+    let body = server.requests()[0].json();
+    assert!(body["prompt"].as_str().unwrap().contains("list files"));
+    assert!(body["prompt"].as_str().unwrap().contains("test env"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_suggests_generate_api_for_old_servers() {
+    let server = StubServer::start(vec![("/api/chat", vec![(404, String::from("404 page not found"))])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("does not support /api/chat"));
+    assert!(stderr(&output).contains("ollama.api = \"generate\""));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_retries_after_invalid_json() {
+    let server = StubServer::start(vec![(
+        "/api/chat",
+        vec![ollama_reply("Sure! Here you go: ls"), ollama_reply(LS_OPTIONS)],
+    )])
+    .await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) ls -la"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    let retry_prompt = requests[1].json()["messages"][2]["content"].as_str().unwrap().to_string();
+    assert!(retry_prompt.contains("Previous attempts failed"));
+    assert!(retry_prompt.contains("Sure! Here you go: ls"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_gives_up_after_max_attempts() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply("not json")])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Failed to generate valid command options after multiple attempts."));
+    assert_eq!(server.requests().len(), 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_sends_options_per_task() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply(LS_OPTIONS)])]).await;
+    let config = ollama_config(&server.url)
+        + "keep_alive = \"30m\"\n\n[ollama.options]\nnum_ctx = 8192\nstop = [\"```\"]\n\n"
+        + "[ollama.options.translate]\ntemperature = 0\nseed = 42\n\n[ollama.options.aliases]\ntemperature = 0.9\n";
+    let env = TestEnv::new(&config);
+
+    let translate = env.run(&["list files"], "").await;
+    let aliases = env.run(&["--alias", "ls -la"], "").await;
+
+    assert!(translate.status.success(), "{}", stderr(&translate));
+    assert!(aliases.status.success(), "{}", stderr(&aliases));
+    let requests = server.requests();
+    let translate = requests[0].json();
+    assert_eq!(translate["keep_alive"], "30m");
+    assert_eq!(translate["options"], json!({ "temperature": 0.0, "seed": 42, "num_ctx": 8192, "stop": ["```"] }));
+    let aliases = requests[1].json();
+    assert_eq!(aliases["options"], json!({ "temperature": 0.9, "num_ctx": 8192, "stop": ["```"] }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_reports_http_errors() {
+    let server = StubServer::start(vec![("/api/chat", vec![(500, String::from(r#"{"error":"out of memory"}"#))])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("API request failed with status: 500"));
+}
+
+const MODEL_NOT_FOUND: &str = r#"{"error":"model \"test-model\" not found, try pulling it first"}"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_offers_to_pull_missing_model() {
+    let pull_progress = [
+        json!({ "status": "pulling manifest" }),
+        json!({ "status": "pulling 6a0746a1ec1a", "total": 2000, "completed": 1000 }),
+        json!({ "status": "pulling 6a0746a1ec1a", "total": 2000, "completed": 2000 }),
+        json!({ "status": "success" }),
+    ]
+    .iter()
+    .map(|update| update.to_string() + "\n")
+    .collect::<String>();
+    let server = StubServer::start(vec![
+        ("/api/chat", vec![(404, String::from(MODEL_NOT_FOUND)), ollama_reply(LS_OPTIONS)]),
+        ("/api/pull", vec![(200, pull_progress)]),
+    ])
+    .await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "y\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Model 'test-model' is not available in Ollama. Pull it now?"));
+    assert!(stdout(&output).contains("Pulled test-model"));
+    assert!(stderr(&output).contains("50%"));
+    assert!(stdout(&output).contains("1) ls -la"));
+    let requests = server.requests();
+    let pull = requests.iter().find(|r| r.path == "/api/pull").unwrap();
+    assert_eq!(pull.json()["model"], "test-model");
+    assert_eq!(requests.iter().filter(|r| r.path == "/api/chat").count(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_reports_missing_model_when_pull_declined() {
+    let server = StubServer::start(vec![("/api/chat", vec![(404, String::from(MODEL_NOT_FOUND))])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["list files"], "n\n").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Model 'test-model' is not available in Ollama at"));
+    assert!(stderr(&output).contains("toli models pull test-model"));
+    assert!(server.requests().iter().all(|r| r.path != "/api/pull"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_pull_reports_errors() {
+    let server = StubServer::start(vec![(
+        "/api/pull",
+        vec![(200, String::from("{\"status\":\"pulling manifest\"}\n{\"error\":\"pull model manifest: file does not exist\"}\n"))],
+    )])
+    .await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["models", "pull", "no-such-model"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to pull 'no-such-model': pull model manifest: file does not exist"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn models_lists_local_ollama_models() {
+    let tags = json!({ "models": [
+        { "name": "test-model:latest", "size": 2_019_393_189u64 },
+        { "name": "qwen2.5-coder:7b", "size": 4_683_087_332u64 },
+    ] });
+    let show = json!({
+        "details": { "parameter_size": "3.2B", "quantization_level": "Q4_K_M" },
+        "model_info": { "general.architecture": "llama", "llama.context_length": 131072 },
+    });
+    let server = StubServer::start(vec![
+        ("/api/tags", vec![(200, tags.to_string())]),
+        ("/api/show", vec![(200, show.to_string())]),
+    ])
+    .await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["models"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let listing = stdout(&output);
+    assert!(listing.contains("CONTEXT"));
+    let line = listing.lines().find(|l| l.starts_with("test-model:latest")).unwrap();
+    assert!(line.contains("2.0 GB"));
+    assert!(line.contains("3.2B"));
+    assert!(line.contains("131072"));
+    assert!(line.ends_with("(configured)"));
+    assert!(listing.lines().any(|l| l.starts_with("qwen2.5-coder:7b") && l.contains("4.7 GB")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_reports_connection_failure() {
+    let env = TestEnv::new(&ollama_config(&unused_port_url()));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to send request"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_explains_command() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply("Shows disk usage.")])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+
+    let output = env.run(&["--explain", "df -h"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Shows disk usage."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_translates_query_with_bearer_token() {
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) ls -la"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].headers["authorization"], "Bearer sk-test-key");
+    let body = requests[0].json();
+    assert_eq!(body["model"], "gpt-test");
+    assert_eq!(body["messages"][1]["content"], "list files");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_retries_unparseable_response() {
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply("I think you want ls")])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Failed to generate valid command options after multiple attempts."));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 5);
+    let retry = requests[1].json();
+    assert_eq!(retry["messages"][1]["content"], "list files");
+    assert!(retry["messages"][2]["content"].as_str().unwrap().contains("I think you want ls"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_accepts_options_wrapped_in_prose() {
+    let reply = format!("Here are some options:\n```json\n{}\n```", LS_OPTIONS);
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(&reply)])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) ls -la"));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_reports_http_errors_without_leaking_key() {
+    let server = StubServer::start(vec![("/chat/completions", vec![(401, String::from(r#"{"error":"bad key"}"#))])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("API request failed with status: 401"));
+    assert!(!stderr(&output).contains("sk-test-key"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_executes_selected_command() {
+    let options = r#"[{"command": "echo via-openai", "explanation": "Prints", "confidence": 0.9}]"#;
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(options)])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["--do", "print something"], "y\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(command_output(&output), "via-openai\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_explains_command_word_by_word() {
+    let reply = r#"{"summary": "Counts entries.", "segments": [{"index": 1, "explanation": "list files"}, {"index": 3, "explanation": "word count"}, {"index": 4, "explanation": "lines only"}]}"#;
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply("not json"), openai_reply(reply)])]).await;
+    let env = TestEnv::new(&openai_config(&server.url));
+
+    let output = env.run(&["--breakdown", "ls | wc -l"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("│  │ │  └─ lines only"));
+    assert!(stdout(&output).contains("│  └─ pipe: passes the output"));
+    let requests = server.requests();
+    let first = requests[0].json();
+    let words = first["messages"][1]["content"].as_str().unwrap();
+    assert!(words.contains("1. ls (program)\n3. wc (program)\n4. -l (flag)"), "{}", words);
+    let retry = requests[1].json()["messages"][2]["content"].as_str().unwrap().to_string();
+    assert!(retry.contains("valid JSON object"));
+}
+
+/// Records usage in `ledger.jsonl` in the test directory, with a price for `gpt-test`.
+fn usage_section(env: &TestEnv, extra: &str) -> String {
+    format!(
+        "\n[usage]\nledger = {:?}\n{}\n[usage.prices.\"gpt-test\"]\ninput = 1.0\noutput = 2.0\n",
+        env.path().join("ledger.jsonl"),
+        extra
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_records_token_usage() {
+    let reply = json!({
+        "choices": [{ "message": { "role": "assistant", "content": LS_OPTIONS } }],
+        "usage": { "prompt_tokens": 412, "completion_tokens": 63, "total_tokens": 475 },
+    });
+    let server = StubServer::start(vec![("/chat/completions", vec![(200, reply.to_string())])]).await;
+    let env = TestEnv::new("");
+    env.write("config.toml", &(openai_config(&server.url) + &usage_section(&env, "")));
+
+    let output = env.run(&["--verbose", "list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Usage: OpenAI/gpt-test translate (prompts v1): 412 prompt + 63 completion tokens"));
+    assert!(stderr(&output).contains("~$0.0005"));
+    let ledger = std::fs::read_to_string(env.path().join("ledger.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(ledger.lines().next().unwrap()).unwrap();
+    assert_eq!(record["model"], "gpt-test");
+    assert_eq!(record["task"], "translate");
+    assert_eq!(record["prompt_version"], "v1");
+    assert_eq!(record["prompt_tokens"], 412);
+    assert_eq!(record["completion_tokens"], 63);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ollama_records_token_usage_and_timings() {
+    let reply = json!({
+        "model": "test-model",
+        "message": { "role": "assistant", "content": LS_OPTIONS },
+        "done": true,
+        "prompt_eval_count": 250,
+        "eval_count": 40,
+        "load_duration": 1_500_000_000u64,
+        "prompt_eval_duration": 120_000_000u64,
+        "eval_duration": 340_000_000u64,
+        "total_duration": 2_000_000_000u64,
+    });
+    let server = StubServer::start(vec![("/api/chat", vec![(200, reply.to_string())])]).await;
+    let env = TestEnv::new("");
+    env.write("config.toml", &(ollama_config(&server.url) + &usage_section(&env, "")));
+
+    let output = env.run(&["-v", "list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Usage: Ollama/test-model translate (prompts v1): 250 prompt + 40 completion tokens"));
+    assert!(stderr(&output).contains("(model load 1.50s, prompt eval 0.12s, eval 0.34s, total 2.00s)"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("$"));
+    let ledger = std::fs::read_to_string(env.path().join("ledger.jsonl")).unwrap();
+    assert!(ledger.contains("\"load_ms\":1500"));
+    assert!(ledger.contains("\"prompt_eval_ms\":120"));
+    assert!(ledger.contains("\"eval_ms\":340"));
+    assert!(ledger.contains("\"total_ms\":2000"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_hosted_requests_over_monthly_budget() {
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new("");
+    env.write("config.toml", &(openai_config(&server.url) + &usage_section(&env, "monthly_budget = 1.0")));
+    let spent = json!({
+        "timestamp": toli::usage::now(),
+        "backend": "OpenAI",
+        "model": "gpt-test",
+        "task": "translate",
+        "prompt_tokens": 1_000_000,
+        "completion_tokens": 0,
+        "elapsed_ms": 900,
+    });
+    env.write("ledger.jsonl", &format!("{}\n", spent));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Monthly budget of $1.00 reached ($1.00 spent this month)"));
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uses_profile_prompt_overrides() {
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&format!("{}\n[profiles.terse]\nmodel = \"gpt-terse\"\n", openai_config(&server.url)));
+    std::fs::create_dir_all(env.path().join("prompts/terse")).unwrap();
+    env.write("prompts/translate.system.txt", "Global override for {{shell}}");
+    env.write("prompts/terse/translate.system.txt", "Terse override. Context: {{context}}");
+    env.write("prompts/translate.user.txt", "Task: {{query}}");
+
+    let output = env.run(&["--profile", "terse", "list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let body = server.requests()[0].json();
+    assert_eq!(body["model"], "gpt-terse");
+    assert_eq!(body["messages"][0]["content"], "Terse override. Context: test env");
+    assert_eq!(body["messages"][1]["content"], "Task: list files");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_unknown_template_variable() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&ollama_config(&server.url));
+    std::fs::create_dir_all(env.path().join("prompts")).unwrap();
+    env.write("prompts/translate.user.txt", "{{qurey}}");
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("In prompt template 'translate.user': unknown variable '{{qurey}}'"));
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn falls_back_when_first_backend_is_unreachable() {
+    let openai = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&fallback_config(&unused_port_url(), &openai.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Ollama/test-model failed (Failed to send request"));
+    assert!(stderr(&output).contains("trying OpenAI/gpt-test"));
+    assert!(stdout(&output).contains("1) ls -la"));
+    assert!(stdout(&output).contains("From: OpenAI/gpt-test"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn falls_back_after_repeated_parse_failures() {
+    let ollama = StubServer::start(vec![("/api/chat", vec![ollama_reply("not json")])]).await;
+    let openai = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&fallback_config(&ollama.url, &openai.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(ollama.requests().len(), 5);
+    assert!(stderr(&output).contains("Ollama/test-model failed (no usable reply)"));
+    assert!(stdout(&output).contains("From: OpenAI/gpt-test"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn falls_back_after_timeout() {
+    // Accepts connections (via the backlog) but never answers.
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent_url = format!("http://{}", silent.local_addr().unwrap());
+    let openai = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&fallback_config(&silent_url, &openai.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Ollama/test-model failed (No answer within 1s)"));
+    assert!(stdout(&output).contains("1) ls -la"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn does_not_fall_back_on_http_errors() {
+    let ollama = StubServer::start(vec![("/api/chat", vec![(500, String::from("{}"))])]).await;
+    let openai = StubServer::start(vec![("/chat/completions", vec![openai_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&fallback_config(&ollama.url, &openai.url));
+
+    let output = env.run(&["list files"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("API request failed with status: 500"));
+    assert!(openai.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ensemble_merges_and_ranks_by_agreement() {
+    let ollama = StubServer::start(vec![(
+        "/api/chat",
+        vec![ollama_reply(r#"[{"command": "ls -la", "explanation": "Lists files", "confidence": 0.85},
+                             {"command": "rm -rf ./*", "explanation": "Clears the directory", "confidence": 0.9}]"#)],
+    )])
+    .await;
+    let openai = StubServer::start(vec![(
+        "/chat/completions",
+        vec![openai_reply(r#"[{"command": "ls -al", "explanation": "Shows all files", "confidence": 0.9},
+                             {"command": "find . -maxdepth 1", "explanation": "Lists entries", "confidence": 0.95}]"#)],
+    )])
+    .await;
+    let env = TestEnv::new(&fallback_config(&ollama.url, &openai.url));
+
+    let output = env.run(&["--ensemble", "list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("1) ls -al\nShows all files\nConfidence: 90% | Risk: low | Category: inspect | From: Ollama/test-model, OpenAI/gpt-test"));
+    assert!(out.contains("2) find . -maxdepth 1"));
+    assert!(out.contains("3) rm -rf ./*"));
+    assert!(out.contains("Warning: high-risk command suggested by only 1 of 2 models"));
+    assert_eq!(ollama.requests().len(), 1);
+    assert_eq!(openai.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ensemble_queries_listed_profiles_and_tolerates_failures() {
+    let server = StubServer::start(vec![("/api/chat", vec![ollama_reply(LS_OPTIONS)])]).await;
+    let env = TestEnv::new(&format!(
+        "ensemble = [\"small\", \"large\", \"offline\"]\n{}\n[profiles.small]\nmodel = \"small-model\"\n\n[profiles.large]\nmodel = \"large-model\"\n\n[profiles.offline]\nendpoint = \"{}\"\n",
+        ollama_config(&server.url),
+        unused_port_url()
+    ));
+
+    let output = env.run(&["--ensemble", "list files"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Ollama/test-model (offline) failed: Failed to send request"));
+    assert!(stdout(&output).contains("From: Ollama/small-model (small), Ollama/large-model (large)"));
+    let mut models: Vec<String> = server.requests().iter().map(|r| r.json()["model"].as_str().unwrap().to_string()).collect();
+    models.sort();
+    assert_eq!(models, ["large-model", "small-model"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openai_requests_are_redacted_and_replies_restored() {
+    let reply = r#"[{"command": "ssh -i ~/.ssh/id REDACTED_EMAIL_1 -p 2222 && ping REDACTED_IP_1", "explanation": "Connects to REDACTED_SECRET_1", "confidence": 0.9}]"#;
+    let server = StubServer::start(vec![("/chat/completions", vec![openai_reply(reply)])]).await;
+    let config = openai_config(&server.url).replace("test env", "host db.corp.internal");
+    let env = TestEnv::new(&format!("{}\n[redact]\npatterns = ['\\b[a-z]+\\.corp\\.internal\\b']\n", config));
+
+    let output = env.run(&["log in as ops@example.com and ping 10.1.2.3"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("1) ssh -i ~/.ssh/id ops@example.com -p 2222 && ping 10.1.2.3"), "{}", out);
+    assert!(out.contains("Connects to db.corp.internal"));
+    let body = server.requests()[0].body.clone();
+    assert!(!body.contains("ops@example.com") && !body.contains("10.1.2.3") && !body.contains("db.corp.internal"), "{}", body);
+    let request = server.requests()[0].json();
+    assert_eq!(request["messages"][1]["content"], "log in as REDACTED_EMAIL_1 and ping REDACTED_IP_1");
+    assert!(request["messages"][0]["content"].as_str().unwrap().contains("host REDACTED_SECRET_1"));
+}
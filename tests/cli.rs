@@ -0,0 +1,430 @@
+mod common;
+
+use common::{command_output, stderr, stdout, TestEnv};
+
+const FIXTURES: &str = r#"{
+    "translate": {
+        "say hello": [
+            {"command": "echo hello-from-toli", "explanation": "Prints a greeting", "confidence": 0.9}
+        ],
+        "pick one": [
+            {"command": "echo first", "explanation": "Prints first", "confidence": 0.95},
+            {"command": "echo second", "explanation": "Prints second", "confidence": 0.9},
+            {"command": "rm -rf /maybe", "explanation": "Not sure", "confidence": 0.2}
+        ],
+        "exit three": [
+            {"command": "sh -c 'exit 3'", "explanation": "Exits with status 3", "confidence": 0.9}
+        ],
+        "set up docker": [
+            {"command": "install-docker.sh", "explanation": "Needs several steps", "confidence": 0.6}
+        ],
+        "loop over files": [
+            {"command": "for f in *; do\n  echo $f\ndone", "explanation": "Prints each file", "confidence": 0.95}
+        ],
+        "write to shared memory": [
+            {"command": "touch /dev/shm/toli-sandbox-escape", "explanation": "Creates a file", "confidence": 0.9}
+        ],
+        "make a file": [
+            {"command": "sh -c 'echo made; touch made-by-toli'", "explanation": "Creates a file", "confidence": 0.9}
+        ],
+        "remove the notes": [
+            {"command": "rm notes.txt", "explanation": "Deletes the notes", "confidence": 0.9}
+        ],
+        "nothing": []
+    },
+    "explain": {
+        "ls -la": "Lists all files, including hidden ones, in long format."
+    },
+    "breakdown": {
+        "tar -xzf backup.tgz > files.txt": {
+            "summary": "Extracts a gzipped archive and saves the listing.",
+            "segments": [
+                {"index": 1, "explanation": "archive tool"},
+                {"index": 2, "explanation": "extract (-x), gunzip (-z), from file (-f)"},
+                {"index": 3, "explanation": "the archive"},
+                {"index": 5, "explanation": "where the output goes"}
+            ]
+        }
+    },
+    "aliases": {
+        "git status": [
+            {"command": "alias gs='git status'", "explanation": "Short git status", "confidence": 1.0}
+        ]
+    }
+}"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shows_options_without_executing_by_default() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["say", "hello"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("1) echo hello-from-toli"));
+    assert!(out.contains("Prints a greeting"));
+    assert!(!out.contains("\nhello-from-toli"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn executes_single_option_after_confirmation() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "say hello"], "\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Execute this command? [Y/n]"));
+    assert_eq!(command_output(&output), "hello-from-toli\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skips_single_option_when_declined() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "say hello"], "n\n").await;
+
+    assert!(output.status.success());
+    assert_eq!(command_output(&output), "\nSkipping command execution.\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sandbox_reports_changes_and_discards_them() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    // The overlay would be made in the temporary directory, here below the
+    // current one, so a copy is used instead.
+    let tmp = env.path().join("tmp");
+    std::fs::create_dir(&tmp).unwrap();
+    let output = env.run_with_env(&["--sandbox", "make a file"], "n\n", &[("TMPDIR", tmp.as_os_str())]).await;
+
+    let err = stderr(&output);
+    if err.contains("sandbox") {
+        eprintln!("skipping, no usable sandbox here: {}", err);
+        return;
+    }
+    assert!(output.status.success(), "{}", err);
+    let out = stdout(&output);
+    assert!(out.contains("full copy, no network): exit status 0"), "{}", out);
+    assert!(out.contains("  made"), "{}", out);
+    assert!(out.contains("created   made-by-toli"), "{}", out);
+    assert!(out.contains("Run it for real? [y/N]"));
+    assert!(!out.contains("Execute this command?"));
+    assert!(!env.path().join("made-by-toli").exists());
+    assert!(!out.contains("toli-sandbox-"), "{}", out);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sandbox_keeps_shared_memory_private() {
+    let escape = std::path::Path::new("/dev/shm/toli-sandbox-escape");
+    if !escape.parent().unwrap().is_dir() {
+        return;
+    }
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--sandbox", "write to shared memory"], "n\n").await;
+
+    let err = stderr(&output);
+    if err.contains("sandbox") {
+        eprintln!("skipping, no usable sandbox here: {}", err);
+        return;
+    }
+    assert!(stdout(&output).contains("exit status 0"), "{}", stdout(&output));
+    assert!(!escape.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn undo_restores_files_removed_by_executed_command() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let notes = env.write("notes.txt", "remember the milk");
+    let output = env.run(&["--do", "remove the notes"], "\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Saved a snapshot of 1 path; 'toli undo' restores it."));
+    assert!(!notes.exists());
+
+    let output = env.run(&["undo"], "y\n").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Before 'rm notes.txt' ran in"), "{}", out);
+    assert!(out.contains("  restore  notes.txt"));
+    assert!(out.contains("Restored 1 path."));
+    assert_eq!(std::fs::read_to_string(&notes).unwrap(), "remember the milk");
+
+    let output = env.run(&["undo"], "").await;
+    assert!(stdout(&output).contains("Nothing to undo."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn audit_logs_executed_commands_and_exports_them() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let config = std::fs::read_to_string(env.path().join("config.toml")).unwrap();
+    let log = env.path().join("shared/audit.jsonl");
+    env.write("config.toml", &format!("{}\n[audit]\nenabled = true\nlog = {:?}\n", config, log));
+    for _ in 0..2 {
+        let output = env.run(&["--do", "exit three"], "\n").await;
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    }
+    let output = env.run(&["say", "hello"], "").await;
+    assert!(output.status.success());
+
+    let output = env.run(&["audit", "verify"], "").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(": 2 records, chain intact."));
+
+    let output = env.run(&["audit", "export", "--format", "json"], "").await;
+    let records: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(records.as_array().unwrap().len(), 2);
+    assert_eq!(records[0]["query"], "exit three");
+    assert_eq!(records[0]["command"], "sh -c 'exit 3'");
+    assert_eq!(records[0]["exit_status"], 3);
+    assert_eq!(records[0]["backend"], "Mock");
+    assert!(records[0]["response"].as_str().unwrap().contains("Exits with status 3"));
+    assert_eq!(records[1]["prev_hash"], records[0]["hash"]);
+
+    let output = env.run(&["audit", "export", "--format", "csv"], "").await;
+    assert_eq!(stdout(&output).lines().count(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn executes_selected_option() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "pick one"], "2\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Select a command to execute (1-3) or 0 to skip"));
+    assert_eq!(command_output(&output), "second\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skips_when_zero_is_selected() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "pick one"], "0\n").await;
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Skipping command execution."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_out_of_range_selection() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "pick one"], "7\n").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid selection"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_to_execute_uncertain_option() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "pick one"], "3\n").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot execute uncertain command"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn propagates_exit_status_of_executed_command() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--do", "exit three"], "y\n").await;
+
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn labels_script_recommendations() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["set up docker"], "").await;
+
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("1) install-docker.sh"));
+    assert!(out.contains("Needs several steps"));
+    assert!(out.contains("This command might need to be part of a script"));
+    assert!(out.contains("Confidence: 60%"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shows_details_of_uncertain_options() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["pick one"], "").await;
+
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("3) rm -rf /maybe (uncertain)\nNot sure\nConfidence: 20% | Risk: high | Category: filesystem"));
+    assert!(out.contains("Confidence: 95% | Risk: low | Category: inspect"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn treats_multi_line_commands_as_scripts() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["loop over files"], "").await;
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("This command might need to be part of a script"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hides_suggestions_below_min_confidence() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--min-confidence", "0.92", "pick one"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("1) echo first"));
+    assert!(!out.contains("echo second"));
+    assert!(!out.contains("rm -rf /maybe"));
+    assert!(out.contains("(2 more below 92% confidence hidden)"));
+
+    let output = env.run(&["--min-confidence", "2", "pick one"], "").await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("expected a number between 0 and 1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uses_configured_thresholds() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let config = std::fs::read_to_string(&env.config_path).unwrap();
+    std::fs::write(&env.config_path, format!("{}\n[thresholds]\ncommand = 0.92\nscript = 0.1\n", config)).unwrap();
+
+    let output = env.run(&["pick one"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("2) echo second\nPrints second\nThis command might need to be part of a script"));
+    assert!(out.contains("3) rm -rf /maybe\n"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_empty_suggestions_as_error() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["nothing"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No valid command options generated"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explains_command() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--explain", "ls -la"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Lists all files, including hidden ones"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn suggests_aliases() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--alias", "git status"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Suggested aliases for 'git status'"));
+    assert!(out.contains("Alias: alias gs='git status'"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_missing_fixture() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["something else"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No mock translate fixture for 'something else'"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_unknown_profile() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--profile", "work", "say hello"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Profile 'work' not found"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn usage_reports_totals_from_ledger() {
+    let env = TestEnv::new("");
+    let ledger = env.path().join("ledger.jsonl");
+    env.write("config.toml", &format!("config_version = 1\n\n[usage]\nledger = {:?}\nmonthly_budget = 5.0\n", ledger));
+    let now = toli::usage::now();
+    let record = |model: &str, backend: &str, prompt_tokens: u64| {
+        format!(
+            r#"{{"timestamp":{},"backend":"{}","model":"{}","task":"translate","prompt_tokens":{},"completion_tokens":100,"elapsed_ms":800}}"#,
+            now, backend, model, prompt_tokens
+        )
+    };
+    env.write(
+        "ledger.jsonl",
+        &[record("gpt-4o-mini", "OpenAI", 1_000_000), record("llama3.2", "Ollama", 500), record("in-house", "OpenAI", 10)].join("\n"),
+    );
+
+    let output = env.run(&["usage"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stdout(&output);
+    let today = toli::usage::date(now);
+    let day = report.lines().find(|l| l.starts_with(&today)).unwrap();
+    assert!(day.contains(" 3 ") && day.contains("1000510") && day.ends_with("$0.1501*"), "{}", day);
+    assert!(report.lines().any(|l| l.starts_with(&today[..7]) && !l.starts_with(&today)));
+    assert!(report.lines().any(|l| l.starts_with("Ollama/llama3.2") && l.ends_with("$0.0000")));
+    assert!(report.contains("models without a price"));
+    assert!(report.contains("Budget: $0.15 of $5.00 used this month"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn breakdown_aligns_explanations_under_words() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--breakdown", "tar -xzf backup.tgz > files.txt"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let expected = "\
+tar -xzf backup.tgz > files.txt
+│   │    │          │ └─ where the output goes
+│   │    │          └─ writes output to the file that follows, replacing its contents
+│   │    └─ the archive
+│   └─ extract (-x), gunzip (-z), from file (-f)
+└─ archive tool
+
+Extracts a gzipped archive and saves the listing.";
+    assert!(stdout(&output).contains(expected), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Risk: medium | Category: filesystem"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn breakdown_falls_back_to_plain_explanation() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let output = env.run(&["--breakdown", "ls -la"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("└─ (no explanation)"));
+    assert!(stdout(&output).contains("Lists all files, including hidden ones, in long format."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explains_command_read_from_stdin() {
+    let env = TestEnv::with_fixtures(r#"{"explain": {"grep -r 'a  b' src": "Searches src for 'a  b'."}}"#);
+    let output = env.run(&["--explain", "-"], "grep -r 'a  b' src\n").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Searches src for 'a  b'."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explains_script_file_in_sections() {
+    let env = TestEnv::with_fixtures(r#"{
+        "explain": {"*": "Runs some steps."},
+        "summary": {"*": "Deploys the app."}
+    }"#);
+    let mut script = String::from("#!/bin/sh\nset -e\n");
+    script.push_str(&"echo building\n".repeat(30));
+    script.push('\n');
+    script.push_str(&"echo copying\n".repeat(20));
+    script.push_str("rm -rf /srv/app/old\n");
+    env.write("deploy.sh", &script);
+    let path = env.path().join("deploy.sh");
+
+    let output = env.run(&["--explain", "--file", path.to_str().unwrap()], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("deploy.sh (54 lines, 2 sections)"), "{}", out);
+    assert!(out.contains("Lines 1-32\nRuns some steps."));
+    assert!(out.contains("Lines 34-54\nRuns some steps."));
+    assert!(out.contains("Summary\nDeploys the app."));
+    assert!(out.contains("line 54: rm -rf /srv/app/old (high risk, filesystem)"));
+}
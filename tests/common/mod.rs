@@ -0,0 +1,211 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::{Output, Stdio};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::process::Command;
+
+/// A request received by `StubServer`.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("request body is not JSON")
+    }
+}
+
+type Routes = HashMap<String, VecDeque<(u16, String)>>;
+
+/// A minimal HTTP/1.1 server standing in for the OpenAI and Ollama APIs.
+/// Each path answers with its queued responses in order; the last one repeats.
+pub struct StubServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl StubServer {
+    pub async fn start(routes: Vec<(&str, Vec<(u16, String)>)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let routes: Arc<Mutex<Routes>> = Arc::new(Mutex::new(
+            routes.into_iter().map(|(path, responses)| (path.to_string(), responses.into())).collect(),
+        ));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else { break };
+                let routes = routes.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let Some(request) = read_request(&mut stream).await else { return };
+                    let (status, body) = {
+                        let mut routes = routes.lock().unwrap();
+                        match routes.get_mut(&request.path) {
+                            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+                            Some(queue) if !queue.is_empty() => queue[0].clone(),
+                            _ => (404, String::from(r#"{"error":"not found"}"#)),
+                        }
+                    };
+                    recorded.lock().unwrap().push(request);
+                    let response = format!(
+                        "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status, body.len(), body
+                    );
+                    stream.write_all(response.as_bytes()).await.ok();
+                    stream.shutdown().await.ok();
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<RecordedRequest> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let content_length: usize = headers.get("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    while buffer.len() < header_end + content_length {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    let body = String::from_utf8_lossy(&buffer[header_end..]).to_string();
+
+    Some(RecordedRequest { method, path, headers, body })
+}
+
+/// An isolated home directory with a config file for running the `toli` binary.
+pub struct TestEnv {
+    dir: TempDir,
+    pub config_path: PathBuf,
+}
+
+impl TestEnv {
+    pub fn new(config: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, config).unwrap();
+        Self { dir, config_path }
+    }
+
+    /// A config using the mock backend with the given fixtures JSON.
+    pub fn with_fixtures(fixtures: &str) -> Self {
+        let env = Self::new("");
+        let fixtures_path = env.write("fixtures.json", fixtures);
+        std::fs::write(
+            &env.config_path,
+            format!("config_version = 1\nbackend = \"Mock\"\n\n[mock]\nfixtures = {:?}\n", fixtures_path),
+        )
+        .unwrap();
+        env
+    }
+
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+
+    /// Runs `toli --config <config> <args>` feeding `stdin` to the process.
+    pub async fn run(&self, args: &[&str], stdin: &str) -> Output {
+        self.run_with_env(args, stdin, &[]).await
+    }
+
+    /// [`TestEnv::run`] with extra environment variables.
+    pub async fn run_with_env(&self, args: &[&str], stdin: &str, vars: &[(&str, &OsStr)]) -> Output {
+        let config = [OsStr::new("--config"), self.config_path.as_os_str()];
+        self.spawn(&config, args, stdin, vars).await
+    }
+
+    /// Runs `toli <args>` without `--config`, so the config is looked up.
+    pub async fn run_unconfigured(&self, args: &[&str], vars: &[(&str, &OsStr)]) -> Output {
+        self.spawn(&[], args, "", vars).await
+    }
+
+    async fn spawn(&self, config: &[&OsStr], args: &[&str], stdin: &str, vars: &[(&str, &OsStr)]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_toli"))
+            .args(config)
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("HOME", self.dir.path())
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("TOLI_CONFIG")
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("XDG_DATA_HOME")
+            .env_remove("OPENAI_API_KEY")
+            .envs(vars.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut child_stdin = child.stdin.take().unwrap();
+        child_stdin.write_all(stdin.as_bytes()).await.unwrap();
+        drop(child_stdin);
+        child.wait_with_output().await.unwrap()
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// The part of stdout printed after toli's execution prompt, i.e. the output
+/// of the executed command (stdin is not echoed, so it follows the prompt directly).
+pub fn command_output(output: &Output) -> String {
+    let out = stdout(output);
+    ["[Y/n]: ", "or 0 to skip: "]
+        .iter()
+        .filter_map(|prompt| out.rfind(prompt).map(|i| out[i + prompt.len()..].to_string()))
+        .next()
+        .unwrap_or_default()
+}
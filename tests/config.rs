@@ -0,0 +1,233 @@
+mod common;
+
+use common::{stderr, stdout, TestEnv};
+
+const FIXTURES: &str = r#"{"translate": {"*": [{"command": "true", "explanation": "Does nothing", "confidence": 0.9}]}}"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn show_masks_api_key() {
+    let env = TestEnv::new("config_version = 1\n\n[openai]\napi_key = \"sk-supersecret-1234\"\n");
+    let output = env.run(&["config", "show"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("sk-…1234"));
+    assert!(!stdout(&output).contains("supersecret"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_masks_api_key_unless_revealed() {
+    let env = TestEnv::new("config_version = 1\n\n[openai]\napi_key = \"sk-supersecret-1234\"\n");
+
+    let key = env.run(&["config", "get", "openai.api_key"], "").await;
+    assert!(key.status.success(), "{}", stderr(&key));
+    assert_eq!(stdout(&key), "sk-…1234\n");
+
+    let section = env.run(&["config", "get", "openai"], "").await;
+    assert!(!stdout(&section).contains("supersecret"));
+
+    let revealed = env.run(&["config", "get", "openai.api_key", "--reveal"], "").await;
+    assert_eq!(stdout(&revealed), "sk-supersecret-1234\n");
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn warns_about_readable_config_until_set_makes_it_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let env = TestEnv::new("config_version = 1\n\n[openai]\napi_key = \"sk-supersecret-1234\"\n");
+    std::fs::set_permissions(&env.config_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let output = env.run(&["usage"], "").await;
+    assert!(stderr(&output).contains("has permissions 644"), "{}", stderr(&output));
+
+    let set = env.run(&["config", "set", "ollama.model", "qwen2.5-coder"], "").await;
+    assert!(set.status.success(), "{}", stderr(&set));
+    let mode = std::fs::metadata(&env.config_path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode, 0o600);
+    let output = env.run(&["usage"], "").await;
+    assert!(!stderr(&output).contains("has permissions"), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_rejects_invalid_values() {
+    let env = TestEnv::new("config_version = 1\nbackend = \"Ollama\"\n");
+    let output = env.run(&["config", "set", "backend", "Nope"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("would make the configuration invalid"));
+    assert!(std::fs::read_to_string(&env.config_path).unwrap().contains("\"Ollama\""));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_rejects_values_the_config_would_not_load_with() {
+    let env = TestEnv::new("config_version = 1\n");
+    let settings = [
+        ("thresholds.command", "1.5", "thresholds.command must be between 0 and 1"),
+        ("thresholds.script", "0.9", "must not be higher than thresholds.command"),
+        ("usage.monthly_budget", "-5", "usage.monthly_budget must not be negative"),
+        ("redact.patterns", "[\"(\"]", "redact.patterns: invalid pattern"),
+        ("backend_timeout", "soon", "would make the configuration invalid"),
+    ];
+    for (key, value, message) in settings {
+        let output = env.run(&["config", "set", key, value], "").await;
+        assert!(!output.status.success(), "config set {} {} succeeded", key, value);
+        assert!(stderr(&output).contains(message), "{}", stderr(&output));
+    }
+    assert_eq!(std::fs::read_to_string(&env.config_path).unwrap(), "config_version = 1\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_stores_values_as_the_type_of_their_field() {
+    let env = TestEnv::new("config_version = 1\n");
+    for (key, value) in [("ollama.model", "3"), ("additional_context", "true"), ("thresholds.command", "0.9"), ("backend_timeout", "30")] {
+        let output = env.run(&["config", "set", key, value], "").await;
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+
+    let config = std::fs::read_to_string(&env.config_path).unwrap();
+    assert!(config.contains("model = \"3\""), "{}", config);
+    assert!(config.contains("additional_context = \"true\""), "{}", config);
+    assert!(config.contains("command = 0.9"), "{}", config);
+    assert!(config.contains("backend_timeout = 30"), "{}", config);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_reads_defaults_for_keys_not_in_the_file() {
+    let env = TestEnv::new("config_version = 1\n");
+
+    let output = env.run(&["config", "get", "thresholds.command"], "").await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "0.8\n");
+
+    let output = env.run(&["config", "get", "backend_timeout"], "").await;
+    assert_eq!(stdout(&output), "60\n");
+
+    let output = env.run(&["config", "get", "nothing.here"], "").await;
+    assert!(stderr(&output).contains("Key 'nothing.here' is not set"), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_then_get_round_trips() {
+    let env = TestEnv::new("config_version = 1\n");
+    let set = env.run(&["config", "set", "ollama.model", "qwen2.5-coder"], "").await;
+    assert!(set.status.success(), "{}", stderr(&set));
+
+    let get = env.run(&["config", "get", "ollama.model"], "").await;
+    assert_eq!(stdout(&get), "qwen2.5-coder\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upgrades_old_config_with_backup() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let current = std::fs::read_to_string(&env.config_path).unwrap();
+    let old = format!(
+        "{}\n[openai]\napi_key = \"your-openai-api-key-here\"\nmodel = \"gpt-4\"\n",
+        current.replace("config_version = 1\n", "")
+    );
+    std::fs::write(&env.config_path, &old).unwrap();
+
+    let output = env.run(&["anything"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Upgraded"));
+    let upgraded = std::fs::read_to_string(&env.config_path).unwrap();
+    assert!(upgraded.contains("config_version = 1"));
+    assert!(!upgraded.contains("your-openai-api-key-here"));
+    let backup = std::fs::read_to_string(env.path().join("config.toml.v0.bak")).unwrap();
+    assert_eq!(backup, old);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_invalid_config_with_line() {
+    let env = TestEnv::new("config_version = 1\nbackend = \"Olama\"\n");
+    let output = env.run(&["anything"], "").await;
+
+    assert!(!output.status.success());
+    let err = stderr(&output);
+    assert!(err.contains("Invalid configuration in"));
+    assert!(err.contains("line 2"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applies_profile_overrides() {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    let fixtures_path = env.path().join("fixtures.json");
+    std::fs::write(
+        &env.config_path,
+        format!(
+            "config_version = 1\nbackend = \"Ollama\"\ndefault_profile = \"test\"\n\n[mock]\nfixtures = {:?}\n\n[profiles.test]\nbackend = \"Mock\"\n",
+            fixtures_path
+        ),
+    )
+    .unwrap();
+
+    let output = env.run(&["anything"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1) true"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_inconsistent_thresholds() {
+    let env = TestEnv::new("config_version = 1\n\n[thresholds]\ncommand = 0.4\nscript = 0.6\n");
+    let output = env.run(&["anything"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("thresholds.script (0.6) must not be higher than thresholds.command (0.4)"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_unknown_backend_in_chain() {
+    let env = TestEnv::new("config_version = 1\nbackend = [\"Ollama\", \"OpenAl\"]\n");
+    let output = env.run(&["anything"], "").await;
+
+    assert!(!output.status.success());
+    let err = stderr(&output);
+    assert!(err.contains("line 2"));
+    assert!(err.contains("unknown variant `OpenAl`"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config_path_prefers_flag_then_env_then_xdg() {
+    let env = TestEnv::new("config_version = 1\n");
+    let from_env = env.path().join("from-env.toml");
+    let xdg = env.path().join("xdg");
+    let path = |output: std::process::Output| stdout(&output).trim().to_string();
+
+    let vars = [("TOLI_CONFIG", from_env.as_os_str()), ("XDG_CONFIG_HOME", xdg.as_os_str())];
+    assert_eq!(path(env.run_with_env(&["config", "path"], "", &vars).await), env.config_path.display().to_string());
+    assert_eq!(path(env.run_unconfigured(&["config", "path"], &vars).await), from_env.display().to_string());
+
+    let vars = [("XDG_CONFIG_HOME", xdg.as_os_str())];
+    assert_eq!(path(env.run_unconfigured(&["config", "path"], &vars).await), xdg.join("toli/config.toml").display().to_string());
+
+    // Relative XDG paths are ignored, as the spec says.
+    let vars = [("XDG_CONFIG_HOME", std::ffi::OsStr::new("relative/xdg"))];
+    let home_config = env.path().join(".config/toli/config.toml");
+    assert_eq!(path(env.run_unconfigured(&["config", "path"], &vars).await), home_config.display().to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn migrates_howto_config_once_from_home_whatever_xdg_says() {
+    let env = TestEnv::new("");
+    let legacy = env.path().join(".config/howto/config.toml");
+    std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+    std::fs::write(&legacy, "backend = \"Ollama\"\n").unwrap();
+    let xdg = env.path().join("xdg");
+    let vars = [("XDG_CONFIG_HOME", xdg.as_os_str())];
+    let config = xdg.join("toli/config.toml");
+
+    let output = env.run_unconfigured(&["config", "path"], &vars).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Moved configuration"));
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), "backend = \"Ollama\"\n");
+    assert!(!legacy.exists());
+    assert!(!legacy.parent().unwrap().exists());
+
+    // An existing config is never replaced by a leftover howto one.
+    std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+    std::fs::write(&legacy, "backend = \"OpenAI\"\n").unwrap();
+    let output = env.run_unconfigured(&["config", "path"], &vars).await;
+    assert!(!stderr(&output).contains("Moved configuration"));
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), "backend = \"Ollama\"\n");
+    assert_eq!(std::fs::read_to_string(&legacy).unwrap(), "backend = \"OpenAI\"\n");
+}
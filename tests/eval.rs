@@ -0,0 +1,127 @@
+mod common;
+
+use common::{stderr, stdout, TestEnv};
+
+const FIXTURES: &str = r#"{
+    "translate": {
+        "long listing": [
+            {"command": "ls -l -a", "explanation": "Long listing", "confidence": 0.9}
+        ],
+        "find pdfs": [
+            {"command": "ls *.pdf", "explanation": "Lists PDFs", "confidence": 0.9},
+            {"command": "find . -name \"*.pdf\"", "explanation": "Finds PDFs", "confidence": 0.9}
+        ],
+        "count files": [
+            {"command": "ls | wc -l", "explanation": "Counts entries", "confidence": 0.9}
+        ],
+        "wrong": [
+            {"command": "rm -rf build", "explanation": "Removes build", "confidence": 0.9}
+        ],
+        "garbled": []
+    }
+}"#;
+
+const DATASET: &str = r#"{"query": "long listing", "expected": "ls -al"}
+{"query": "find pdfs", "expected": ["find . -name '*.pdf'"]}
+{"query": "count files", "expected": "find . -mindepth 1 -maxdepth 1 | wc -l", "fixture": "fixture"}
+{"query": "wrong", "expected": "make clean"}
+{"query": "garbled", "expected": "true"}
+"#;
+
+fn setup() -> TestEnv {
+    let env = TestEnv::with_fixtures(FIXTURES);
+    env.write("dataset.jsonl", DATASET);
+    std::fs::create_dir(env.path().join("fixture")).unwrap();
+    env.write("fixture/a.txt", "a");
+    env.write("fixture/b.txt", "b");
+    env
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_accuracy_by_normalized_comparison() {
+    let env = setup();
+    let output = env.run(&["eval", "dataset.jsonl", "--json"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let model = &report[0];
+    assert_eq!(model["model"], "Mock");
+    assert_eq!(model["cases"], 5);
+    assert_eq!(model["top1_correct"], 1);
+    assert_eq!(model["any_correct"], 2);
+    assert_eq!(model["correct_by_execution"], 0);
+    assert_eq!(model["errors"], 1);
+    assert_eq!(model["latencies_ms"].as_array().unwrap().len(), 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn execute_counts_commands_with_same_effect() {
+    let env = setup();
+    let output = env.run(&["eval", "dataset.jsonl", "--json", "--execute"], "").await;
+    if stderr(&output).contains("sandbox") {
+        eprintln!("skipping, no usable sandbox here: {}", stderr(&output));
+        return;
+    }
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report[0]["top1_correct"], 2);
+    assert_eq!(report[0]["correct_by_execution"], 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn execute_runs_commands_in_a_sandbox() {
+    let outside = tempfile::tempdir().unwrap();
+    let target = outside.path().join("keep.txt");
+    std::fs::write(&target, "keep").unwrap();
+    let fixtures = format!(
+        r#"{{"translate": {{
+            "numbers": [{{"command": "seq 100000", "explanation": "Counts", "confidence": 0.9}}],
+            "clean up": [{{"command": "rm -f {}", "explanation": "Removes", "confidence": 0.9}}]
+        }}}}"#,
+        target.display()
+    );
+    let env = TestEnv::with_fixtures(&fixtures);
+    env.write(
+        "dataset.jsonl",
+        "{\"query\": \"numbers\", \"expected\": \"seq 1 100000\", \"fixture\": \"fixture\"}\n\
+         {\"query\": \"clean up\", \"expected\": \"true\", \"fixture\": \"fixture\"}\n",
+    );
+    std::fs::create_dir(env.path().join("fixture")).unwrap();
+
+    let output = env.run(&["eval", "dataset.jsonl", "--json", "--execute"], "").await;
+    if stderr(&output).contains("sandbox") {
+        eprintln!("skipping, no usable sandbox here: {}", stderr(&output));
+        return;
+    }
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    // More output than a pipe holds doesn't stall the command.
+    assert_eq!(report[0]["correct_by_execution"], 1);
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_table_per_profile() {
+    let env = setup();
+    let config = std::fs::read_to_string(&env.config_path).unwrap();
+    std::fs::write(&env.config_path, format!("{}\n[profiles.a]\nbackend = \"Mock\"\n\n[profiles.b]\nbackend = \"Mock\"\n", config)).unwrap();
+
+    let output = env.run(&["eval", "dataset.jsonl", "--profile", "a", "--profile", "b"], "").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Mock (a)"));
+    assert!(out.contains("Mock (b)"));
+    assert!(out.contains("top-1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_dataset_line() {
+    let env = setup();
+    env.write("bad.jsonl", "{\"query\": \"x\"}\n");
+    let output = env.run(&["eval", "bad.jsonl"], "").await;
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("bad.jsonl:1: invalid dataset entry"));
+}
@@ -0,0 +1,306 @@
+use toli::shell::{
+    self, Assignment, Command, CompoundCommand, Connector, List, RedirectOp, RedirectTarget, SimpleCommand, Value, Word,
+    WordPart,
+};
+
+fn parse(source: &str) -> List {
+    shell::parse(source).unwrap_or_else(|e| panic!("failed to parse {:?}: {}", source, e))
+}
+
+fn error(source: &str) -> String {
+    shell::parse(source).expect_err(source).to_string()
+}
+
+/// The single simple command of `source`.
+fn simple(source: &str) -> SimpleCommand {
+    match &parse(source).items[..] {
+        [item] if item.rest.is_empty() => match &item.first.commands[..] {
+            [Command::Simple(simple)] => simple.clone(),
+            other => panic!("expected one simple command, got {:?}", other),
+        },
+        other => panic!("expected one command, got {:?}", other),
+    }
+}
+
+fn values(words: &[Word]) -> Vec<String> {
+    words.iter().map(Word::value).collect()
+}
+
+/// The programs of all simple commands, nested ones included.
+fn programs(source: &str) -> Vec<String> {
+    parse(source).simple_commands().iter().filter_map(|c| c.program()).collect()
+}
+
+fn compound(source: &str) -> CompoundCommand {
+    match &parse(source).items[0].first.commands[0] {
+        Command::Compound(compound, _) => compound.clone(),
+        other => panic!("expected a compound command, got {:?}", other),
+    }
+}
+
+#[test]
+fn splits_words_and_removes_quotes() {
+    let command = simple(r#"grep -r "a  b" 'c d' e\ f"#);
+    assert_eq!(values(&command.words), ["grep", "-r", "a  b", "c d", "e f"]);
+    assert_eq!(command.words[2].span, 8..14);
+    assert_eq!(command.span, 0..25);
+    assert!(command.words[3].is_quoted(r#"grep -r "a  b" 'c d' e\ f"#));
+    assert_eq!(command.words[3].literal().as_deref(), Some("c d"));
+}
+
+#[test]
+fn parses_lists_and_pipelines() {
+    let list = parse("make && make install || echo failed; sleep 1 & ls | wc -l");
+    assert_eq!(list.items.len(), 3);
+
+    let first = &list.items[0];
+    let connectors: Vec<Connector> = first.rest.iter().map(|(c, _)| *c).collect();
+    assert_eq!(connectors, [Connector::And, Connector::Or]);
+    assert!(list.items[1].background);
+    assert!(!list.items[2].background);
+    assert_eq!(list.items[2].first.commands.len(), 2);
+
+    let negated = parse("! grep -q x file");
+    assert!(negated.items[0].first.negated);
+
+    let multi_line = parse("cd /srv\n\n# deploy\ngit pull |\n  tee log\n");
+    assert_eq!(multi_line.items.len(), 2);
+    assert_eq!(programs("cd /srv\n\n# deploy\ngit pull |\n  tee log\n"), ["cd", "git", "tee"]);
+}
+
+#[test]
+fn parses_redirections() {
+    let command = simple("sort -u < in.txt > out.txt 2>&1 2>>err.log &>all.log");
+    assert_eq!(values(&command.words), ["sort", "-u"]);
+    let redirects: Vec<_> = command
+        .redirects
+        .iter()
+        .map(|r| match &r.target {
+            RedirectTarget::Word(word) => (r.fd, r.op, word.value()),
+            RedirectTarget::HereDoc(_) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        redirects,
+        [
+            (None, RedirectOp::Input, String::from("in.txt")),
+            (None, RedirectOp::Output, String::from("out.txt")),
+            (Some(2), RedirectOp::DupOutput, String::from("1")),
+            (Some(2), RedirectOp::Append, String::from("err.log")),
+            (None, RedirectOp::OutputAndError, String::from("all.log")),
+        ]
+    );
+
+    // A number is only a file descriptor when the operator follows it directly.
+    let command = simple("echo 2 > two");
+    assert_eq!(values(&command.words), ["echo", "2"]);
+    assert_eq!(command.redirects[0].fd, None);
+
+    let command = simple("cat <<< 'hello'");
+    assert_eq!(command.redirects[0].op, RedirectOp::HereString);
+}
+
+#[test]
+fn parses_assignments() {
+    let command = simple(r#"LANG=C PATH="$HOME/bin:$PATH" sort file"#);
+    let names: Vec<&str> = command.assignments.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, ["LANG", "PATH"]);
+    match &command.assignments[1].value {
+        Value::Scalar(word) => assert_eq!(word.value(), "$HOME/bin:$PATH"),
+        other => panic!("expected a scalar, got {:?}", other),
+    }
+    assert_eq!(values(&command.words), ["sort", "file"]);
+
+    let command = simple("files=(a.txt 'b c.txt') count+=1");
+    match &command.assignments[..] {
+        [Assignment { name, value: Value::Array(items), .. }, Assignment { name: count, .. }] => {
+            assert_eq!(name, "files");
+            assert_eq!(values(items), ["a.txt", "b c.txt"]);
+            assert_eq!(count, "count");
+        }
+        other => panic!("unexpected assignments {:?}", other),
+    }
+    assert!(command.words.is_empty());
+
+    // Only words before the program are assignments.
+    let command = simple("env FOO=1 make");
+    assert!(command.assignments.is_empty());
+    assert_eq!(values(&command.words), ["env", "FOO=1", "make"]);
+}
+
+#[test]
+fn parses_substitutions() {
+    let command = simple(r#"echo "today is $(date +%F)" `whoami` $((1 + 2)) ${USER:-nobody} $'a\'b'"#);
+    assert_eq!(
+        values(&command.words),
+        ["echo", "today is $(date +%F)", "`whoami`", "$((1 + 2))", "${USER:-nobody}", "a\\'b"]
+    );
+    match &command.words[1].parts[0] {
+        WordPart::DoubleQuoted(parts) => {
+            assert_eq!(parts[0], WordPart::Literal(String::from("today is ")));
+            assert!(matches!(&parts[1], WordPart::CommandSubstitution { .. }));
+        }
+        other => panic!("expected a double-quoted part, got {:?}", other),
+    }
+    assert_eq!(command.words[3].parts, [WordPart::Arithmetic(String::from("1 + 2"))]);
+    assert_eq!(command.words[1].literal(), None);
+    assert_eq!(command.words[1].substitutions().len(), 1);
+
+    assert_eq!(programs("echo $(basename $(pwd))"), ["echo", "basename", "pwd"]);
+    assert_eq!(programs("echo `ls | wc -l`"), ["echo", "ls", "wc"]);
+    assert_eq!(programs("diff <(sort a) >(tee b)"), ["diff", "sort", "tee"]);
+    assert_eq!(programs("echo \"$(case $x in a) echo a;; esac)\""), ["echo", "echo"]);
+
+    // Statements leave out what runs inside substitutions.
+    let list = parse("if true; then f() { rm x; }; fi\necho $(ls)");
+    let statements: Vec<_> = list
+        .statements()
+        .iter()
+        .flat_map(|pipeline| &pipeline.commands)
+        .filter_map(|command| match command {
+            Command::Simple(simple) => simple.program(),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(statements, ["true", "rm", "echo"]);
+}
+
+#[test]
+fn parses_here_documents() {
+    let source = "cat <<EOF > /etc/motd\nHello $USER\n\nbye\nEOF\ncat <<-'END' | sort\n\tb\n\ta\n\tEND\necho done\n";
+    let list = parse(source);
+    assert_eq!(list.items.len(), 3);
+
+    let heredocs: Vec<_> = list
+        .simple_commands()
+        .iter()
+        .flat_map(|c| &c.redirects)
+        .filter_map(|r| match &r.target {
+            RedirectTarget::HereDoc(heredoc) => Some(heredoc.clone()),
+            RedirectTarget::Word(_) => None,
+        })
+        .collect();
+    assert_eq!(heredocs.len(), 2);
+    assert_eq!(heredocs[0].delimiter, "EOF");
+    assert_eq!(heredocs[0].body, "Hello $USER\n\nbye\n");
+    assert_eq!(&source[heredocs[0].span.clone()], "Hello $USER\n\nbye\nEOF\n");
+    assert!(!heredocs[0].quoted);
+    assert_eq!(heredocs[1].body, "b\na\n");
+    assert_eq!(&source[heredocs[1].span.clone()], "\tb\n\ta\n\tEND\n");
+    assert_eq!(list.here_docs().len(), 2);
+    assert!(heredocs[1].quoted && heredocs[1].strip_tabs);
+    assert_eq!(programs(source), ["cat", "cat", "sort", "echo"]);
+
+    // Two here-documents on one line are read one after the other.
+    let list = parse("paste <<A <<B\n1\nA\n2\nB\nls\n");
+    assert_eq!(list.items.len(), 2);
+    let bodies: Vec<_> = list.simple_commands()[0]
+        .redirects
+        .iter()
+        .map(|r| match &r.target {
+            RedirectTarget::HereDoc(heredoc) => heredoc.body.clone(),
+            RedirectTarget::Word(_) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(bodies, ["1\n", "2\n"]);
+}
+
+#[test]
+fn parses_compound_commands() {
+    match compound("if [ -f a ]; then echo a; elif test -d b\nthen echo b; else echo none; fi") {
+        CompoundCommand::If { branches, otherwise } => {
+            assert_eq!(branches.len(), 2);
+            assert!(otherwise.is_some());
+        }
+        other => panic!("expected if, got {:?}", other),
+    }
+    match compound("for f in *.txt \"my file\"; do\n  wc -l \"$f\"\ndone") {
+        CompoundCommand::For { select, variable, words, body } => {
+            assert!(!select);
+            assert_eq!(variable, "f");
+            assert_eq!(values(&words.unwrap()), ["*.txt", "my file"]);
+            assert_eq!(body.items.len(), 1);
+        }
+        other => panic!("expected for, got {:?}", other),
+    }
+    match compound("case \"$1\" in\n  start|up) run;;\n  (stop) halt ;;\n  *) ;;\nesac") {
+        CompoundCommand::Case { word, arms } => {
+            assert_eq!(word.value(), "$1");
+            assert_eq!(arms.len(), 3);
+            assert_eq!(values(&arms[0].patterns), ["start", "up"]);
+            assert!(arms[2].body.items.is_empty());
+        }
+        other => panic!("expected case, got {:?}", other),
+    }
+    assert!(matches!(compound("until ping -c1 host; do sleep 1; done"), CompoundCommand::While { until: true, .. }));
+    match compound("select x in a b; do echo $x; done") {
+        CompoundCommand::For { select, variable, words, body } => {
+            assert!(select);
+            assert_eq!(variable, "x");
+            assert_eq!(values(&words.unwrap()), ["a", "b"]);
+            assert_eq!(body.items.len(), 1);
+        }
+        other => panic!("expected select, got {:?}", other),
+    }
+    assert!(matches!(compound("{ ls; pwd; }"), CompoundCommand::Group(list) if list.items.len() == 2));
+    assert!(matches!(compound("(cd /tmp && ls)"), CompoundCommand::Subshell(_)));
+    assert!(matches!(compound("for ((i = 0; i < 3; i++)); do echo $i; done"), CompoundCommand::ArithmeticFor { .. }));
+    assert_eq!(compound("(( n > 3 ))"), CompoundCommand::Arithmetic(String::from(" n > 3 ")));
+    match compound("[[ $a < $b && -n $c ]]") {
+        CompoundCommand::Conditional(words) => assert_eq!(values(&words), ["$a", "<", "$b", "&&", "-n", "$c"]),
+        other => panic!("expected [[, got {:?}", other),
+    }
+
+    let list = parse("while read line; do echo \"$line\"; done < input.txt > output.txt");
+    assert_eq!(list.items[0].first.commands[0].redirects().len(), 2);
+    assert_eq!(programs("if true; then rm -rf build; fi"), ["true", "rm"]);
+}
+
+#[test]
+fn parses_function_definitions() {
+    for source in ["greet() { echo hi; }", "function greet { echo hi; }", "function greet() {\n  echo hi\n}"] {
+        match &parse(source).items[0].first.commands[0] {
+            Command::Function { name, body } => {
+                assert_eq!(name, "greet");
+                assert!(matches!(**body, Command::Compound(CompoundCommand::Group(_), _)));
+            }
+            other => panic!("expected a function in {:?}, got {:?}", source, other),
+        }
+    }
+    assert_eq!(programs("cleanup() { rm -f \"$tmp\"; }; cleanup"), ["rm", "cleanup"]);
+}
+
+#[test]
+fn keeps_glob_groups_and_quoted_braces_in_words() {
+    assert_eq!(values(&simple("ls *(.)").words), ["ls", "*(.)"]);
+    assert_eq!(values(&simple("ls -d *.txt(N) [ab]*(/)").words), ["ls", "-d", "*.txt(N)", "[ab]*(/)"]);
+    assert_eq!(values(&simple("rm !(*.txt|*.md)").words), ["rm", "!(*.txt|*.md)"]);
+    assert_eq!(values(&simple(r#"echo ${x:-"}"}"#).words), ["echo", r#"${x:-"}"}"#]);
+    assert_eq!(values(&simple(r#"echo "${x:-'}""#).words), ["echo", "${x:-'}"]);
+    assert_eq!(values(&simple(r"echo ${x:-\}} done").words), ["echo", r"${x:-\}}", "done"]);
+    assert_eq!(programs("f() { ls; }; f"), ["ls", "f"]);
+}
+
+#[test]
+fn keeps_reserved_words_as_arguments() {
+    assert_eq!(values(&simple("echo if then fi }").words), ["echo", "if", "then", "fi", "}"]);
+    assert_eq!(values(&simple("echo {a,b} a#b").words), ["echo", "{a,b}", "a#b"]);
+    assert_eq!(values(&simple("ls # a comment").words), ["ls"]);
+}
+
+#[test]
+fn reports_syntax_errors() {
+    assert_eq!(error("echo 'unterminated"), "unterminated single quote (line 1, column 6)");
+    assert_eq!(error("echo \"$(date"), "expected ')', found end of input (line 1, column 13)");
+    assert_eq!(error("ls |"), "expected a command, found end of input (line 1, column 5)");
+    assert_eq!(error("ls && || wc"), "expected a command, found '||' (line 1, column 7)");
+    assert_eq!(error("if true; then ls"), "expected 'fi', found end of input (line 1, column 17)");
+    assert_eq!(error("ls\nfi"), "unexpected 'fi' (line 2, column 1)");
+    assert_eq!(error("echo )"), "unexpected ')' (line 1, column 6)");
+    assert_eq!(error("cat <file>"), "expected a target after '>', found end of input (line 1, column 11)");
+    assert_eq!(error("for 1 in a; do :; done"), "expected a variable name after 'for', found '1' (line 1, column 5)");
+    assert_eq!(error("ls *(.\n"), "unterminated glob pattern '(' (line 1, column 5)");
+    assert_eq!(error("echo ${x:-\"}"), "unterminated '${' (line 1, column 6)");
+    assert!(shell::parse("").unwrap().items.is_empty());
+    assert!(shell::parse("  # only a comment\n").unwrap().items.is_empty());
+}